@@ -0,0 +1,76 @@
+//! Times scene intersection from every thread at once, with all objects sharing one material.
+//! Hit records borrow their material, so this compares against spheres that also clone the material's `Arc`
+//! for each candidate hit and drop it again, as records used to, which makes every thread fight over the same reference count.
+
+use std::{hint::black_box, sync::Arc, time::{Duration, Instant}};
+use rayon::prelude::*;
+use raytrace::{environment::Gradient, materials::*, objects::*, scene::Scene, util::*, vec::*};
+
+const RAYS: usize = 2_000_000;
+const RUNS: usize = 5;
+
+/// A sphere that clones its material for every hit it finds, as `Sphere::hit` did when records owned their material
+struct CloningSphere {
+    sphere: Sphere,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Hittable for CloningSphere {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let hit = self.sphere.hit(ray, tmin, tmax)?;
+        // The old record held the clone until it was replaced by a closer hit or dropped
+        black_box(Arc::clone(&self.material));
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sphere.bounding_box()
+    }
+}
+
+fn main() {
+    let material: Arc<dyn Material + Send + Sync> = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+    let mut spheres = vec![(Vec3(0.0, -1000.0, 0.0), 1000.0)];
+    for a in -5..5 {
+        for b in -5..5 {
+            spheres.push((Vec3(a as Float, 0.3, b as Float), 0.3));
+        }
+    }
+    let scene = |cloning: bool| {
+        let mut hittables = Hittables::new();
+        for &(center, radius) in spheres.iter() {
+            let sphere = Sphere::new(center, radius, material.clone());
+            if cloning {
+                hittables.push(Box::new(CloningSphere { sphere, material: material.clone() }));
+            } else {
+                hittables.push(Box::new(sphere));
+            }
+        }
+        Scene::new(hittables, Box::new(Gradient::new(Vec3(1.0, 1.0, 1.0), Vec3(0.3, 0.5, 1.0))))
+    };
+    let (borrowing, cloning) = (scene(false), scene(true));
+    // Looking down from above, so that nearly every ray hits something
+    let rays: Vec<Ray> = (0..RAYS).map(|_| {
+        let direction = random_unit_vector();
+        Ray::new(&Vec3(0.0, 5.0, 0.0), &Vec3(direction.0, -direction.1.abs(), direction.2))
+    }).collect();
+
+    println!("Tracing {} rays on {} threads", RAYS, rayon::current_num_threads());
+    let trace = |scene: &Scene| time(|| rays.par_iter().filter(|ray| {
+        scene.hit(ray, 0.0001, Float::INFINITY).is_some_and(|hit| !hit.material().is_delta())
+    }).count());
+    let borrowed = trace(&borrowing);
+    let cloned = trace(&cloning);
+
+    println!("borrowed material:   {:>8.2} Mrays/s", RAYS as f64 / borrowed.as_secs_f64() / 1e6);
+    println!("Arc clone per hit:   {:>8.2} Mrays/s", RAYS as f64 / cloned.as_secs_f64() / 1e6);
+}
+
+/// Best of several runs, to keep the numbers steady
+fn time(f: impl Fn() -> usize) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        assert!(f() > 0, "Rays should hit the scene");
+        start.elapsed()
+    }).min().unwrap()
+}
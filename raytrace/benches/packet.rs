@@ -0,0 +1,43 @@
+//! Times finding the first hit of camera rays one at a time against tracing them as packets through the BVH
+
+use std::{sync::Arc, time::{Duration, Instant}};
+use raytrace::{camera::Camera, environment::Gradient, materials::*, objects::*, scene::Scene, simd::LANES, vec::*};
+
+const WIDTH: usize = 512;
+const HEIGHT: usize = 288;
+const RUNS: usize = 5;
+
+fn main() {
+    let material: Arc<dyn Material + Send + Sync> = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+    let mut hittables = Hittables::new();
+    hittables.push(Box::new(Sphere::new(Vec3(0.0, -1000.0, 0.0), 1000.0, material.clone())));
+    for a in -11..11 {
+        for b in -11..11 {
+            hittables.push(Box::new(Sphere::new(Vec3(a as Float, 0.2, b as Float), 0.2, material.clone())));
+        }
+    }
+    let scene = Scene::new(hittables, Box::new(Gradient::new(Vec3(1.0, 1.0, 1.0), Vec3(0.3, 0.5, 1.0))));
+    let camera = Camera::new(Vec3(13.0, 2.0, 3.0), Vec3(0.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), 20.0, WIDTH as Float / HEIGHT as Float, 0.0, 10.0);
+    // A packet per pixel, each ray jittered within it
+    let packets: Vec<Vec<Ray>> = (0..WIDTH * HEIGHT).map(|i| {
+        let (x, y) = ((i % WIDTH) as Float, (i / WIDTH) as Float);
+        (0..LANES).map(|_| camera.get_ray((x + rand::random::<Float>()) / WIDTH as Float, (y + rand::random::<Float>()) / HEIGHT as Float)).collect()
+    }).collect();
+    let rays = (WIDTH * HEIGHT * LANES) as f64;
+
+    println!("Tracing {} camera rays in packets of {}", rays, LANES);
+    let single = time(|| packets.iter().flatten().filter(|ray| scene.hit(ray, 0.0001, Float::INFINITY).is_some()).count());
+    let packet = time(|| packets.iter().map(|rays| scene.hit_packet(rays, 0.0001, Float::INFINITY).iter().flatten().count()).sum());
+
+    println!("one ray at a time:   {:>8.2} Mrays/s", rays / single.as_secs_f64() / 1e6);
+    println!("packets:             {:>8.2} Mrays/s", rays / packet.as_secs_f64() / 1e6);
+}
+
+/// Best of several runs, to keep the numbers steady
+fn time(f: impl Fn() -> usize) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        assert!(f() > 0, "Rays should hit the scene");
+        start.elapsed()
+    }).min().unwrap()
+}
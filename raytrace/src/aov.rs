@@ -0,0 +1,267 @@
+use crate::{exr, objects::HitRecord, vec::{Color, Float, Vec3}};
+use std::{io, path::Path};
+
+/// What a camera ray first hit, as seen by the passes that describe the scene rather than its lighting
+#[derive(Debug, Clone, Copy)]
+pub struct Surface {
+    pub albedo: Color,
+    /// Shading normal, facing the camera
+    pub normal: Vec3,
+    /// Distance from the camera
+    pub depth: Float,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl Surface {
+    pub fn new(hit: &HitRecord) -> Self {
+        let material = hit.material();
+        Self {
+            albedo: material.albedo(hit),
+            normal: material.shading_normal(hit),
+            depth: hit.distance(),
+            object_id: hit.object_id(),
+            material_id: material_id(material as *const _ as *const () as usize)
+        }
+    }
+}
+
+/// A number for each material, found from where it is in memory.
+/// It tells materials apart within one render, but is not the same from one render to the next.
+fn material_id(address: usize) -> u32 {
+    // Fibonacci hashing, kept to 24 bits so that it survives being stored as a float.
+    // Zero is left for rays that hit nothing.
+    let hash = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40;
+    (hash as u32).max(1)
+}
+
+/// The passes of one camera ray.
+/// Its light is split three ways that sum to the beauty image: light given off by what it hit,
+/// light reaching there straight from a light or the environment, and light that bounced more than once.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Light given off by the surface hit, or by the environment for rays that hit nothing
+    pub emission: Color,
+    pub direct: Color,
+    pub indirect: Color,
+    /// `None` for rays that hit nothing
+    pub surface: Option<Surface>,
+}
+
+impl Sample {
+    pub fn beauty(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+}
+
+/// Running totals of the samples taken in one pixel, so that the samples themselves need not be kept
+#[derive(Debug, Clone)]
+pub struct Pixel {
+    samples: usize,
+    emission: Color,
+    direct: Color,
+    indirect: Color,
+    albedo: Color,
+    normal: Vec3,
+    depth: Float,
+    // Samples that hit something, which depth is averaged over
+    hits: usize,
+    // How many samples saw each ID, with 0 for rays that hit nothing
+    object_ids: Vec<(u32, usize)>,
+    material_ids: Vec<(u32, usize)>,
+}
+
+impl Default for Pixel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pixel {
+    pub fn new() -> Self {
+        let black = Vec3(0.0, 0.0, 0.0);
+        Self {
+            samples: 0,
+            emission: black,
+            direct: black,
+            indirect: black,
+            albedo: black,
+            normal: black,
+            depth: 0.0,
+            hits: 0,
+            object_ids: vec![],
+            material_ids: vec![]
+        }
+    }
+
+    pub fn add(&mut self, sample: &Sample) {
+        self.samples += 1;
+        self.emission = self.emission + sample.emission;
+        self.direct = self.direct + sample.direct;
+        self.indirect = self.indirect + sample.indirect;
+        if let Some(surface) = &sample.surface {
+            self.albedo = self.albedo + surface.albedo;
+            self.normal = self.normal + surface.normal;
+            self.depth += surface.depth;
+            self.hits += 1;
+        }
+        count(&mut self.object_ids, sample.surface.map_or(0, |s| s.object_id));
+        count(&mut self.material_ids, sample.surface.map_or(0, |s| s.material_id));
+    }
+}
+
+/// Adds one to the count of `id`, which only a handful of objects in one pixel keeps short
+fn count(counts: &mut Vec<(u32, usize)>, id: u32) {
+    match counts.iter_mut().find(|(other, _)| *other == id) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((id, 1))
+    }
+}
+
+/// The ID counted the most, preferring the higher ID on a tie so the result does not depend on sample order
+fn most_common(counts: &[(u32, usize)]) -> u32 {
+    counts.iter().max_by_key(|&&(id, n)| (n, id)).map_or(0, |&(id, _)| id)
+}
+
+/// A rectangle of pixels, counting from the top left of the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// An image of every pass, each pixel averaging the samples taken in it
+pub struct Passes {
+    width: usize,
+    height: usize,
+    // Size of the whole frame, and where in it the passes start, for renders of only part of it
+    frame: (usize, usize),
+    origin: (usize, usize),
+    beauty: Vec<Color>,
+    emission: Vec<Color>,
+    direct: Vec<Color>,
+    indirect: Vec<Color>,
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+    depth: Vec<Float>,
+    object_id: Vec<u32>,
+    material_id: Vec<u32>,
+}
+
+impl Passes {
+    pub fn new(width: usize, height: usize) -> Self {
+        let black = vec![Vec3(0.0, 0.0, 0.0); width * height];
+        Self {
+            width,
+            height,
+            frame: (width, height),
+            origin: (0, 0),
+            beauty: black.clone(),
+            emission: black.clone(),
+            direct: black.clone(),
+            indirect: black.clone(),
+            albedo: black.clone(),
+            normal: black,
+            depth: vec![Float::INFINITY; width * height],
+            object_id: vec![0; width * height],
+            material_id: vec![0; width * height]
+        }
+    }
+
+    /// Passes covering only `region` of a frame, such as to look closely at one object without rendering everything.
+    /// Pixels are still set counting from the region's top left.
+    pub fn cropped(frame_width: usize, frame_height: usize, region: Region) -> Self {
+        assert!(region.width > 0 && region.height > 0, "Regions must have at least one pixel");
+        assert!(region.x + region.width <= frame_width && region.y + region.height <= frame_height, "Regions must lie within the frame");
+        Self {
+            frame: (frame_width, frame_height),
+            origin: (region.x, region.y),
+            ..Self::new(region.width, region.height)
+        }
+    }
+
+    /// The part of the frame the passes cover
+    pub fn region(&self) -> Region {
+        Region {
+            x: self.origin.0,
+            y: self.origin.1,
+            width: self.width,
+            height: self.height
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Sets a pixel, counting from the top left, from the samples taken in it.
+    /// Colors and normals are averaged, counting rays that hit nothing as black and zero.
+    /// Depth is averaged over the rays that hit something, and is infinite where none did.
+    /// IDs are the ones most rays saw, since an average of two IDs means nothing.
+    pub fn set(&mut self, x: usize, y: usize, pixel: &Pixel) {
+        assert!(x < self.width && y < self.height, "Pixel ({}, {}) is outside of the image", x, y);
+        assert!(pixel.samples > 0, "A pixel needs at least one sample");
+        let i = y * self.width + x;
+        let scale = 1.0 / pixel.samples as Float;
+
+        self.beauty[i] = scale * (pixel.emission + pixel.direct + pixel.indirect);
+        self.emission[i] = scale * pixel.emission;
+        self.direct[i] = scale * pixel.direct;
+        self.indirect[i] = scale * pixel.indirect;
+        self.albedo[i] = scale * pixel.albedo;
+        self.normal[i] = scale * pixel.normal;
+        self.depth[i] = if pixel.hits == 0 { Float::INFINITY } else { pixel.depth / pixel.hits as Float };
+        self.object_id[i] = most_common(&pixel.object_ids);
+        self.material_id[i] = most_common(&pixel.material_ids);
+    }
+
+    pub fn beauty(&self) -> &[Color] {
+        &self.beauty
+    }
+
+    pub fn emission(&self) -> &[Color] {
+        &self.emission
+    }
+
+    pub fn albedo(&self) -> &[Color] {
+        &self.albedo
+    }
+
+    pub fn normal(&self) -> &[Vec3] {
+        &self.normal
+    }
+
+    pub fn depth(&self) -> &[Float] {
+        &self.depth
+    }
+
+    /// Writes every pass as a layer of one OpenEXR file, with the beauty image as its main RGB channels.
+    /// Passes covering part of a frame keep their place in it.
+    pub fn write_exr(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut channels: Vec<(String, Vec<f32>)> = vec![];
+        let mut add_color = |layer: &str, names: [&str; 3], pixels: &[Vec3]| {
+            for (axis, name) in names.iter().enumerate() {
+                let name = if layer.is_empty() { name.to_string() } else { format!("{}.{}", layer, name) };
+                channels.push((name, pixels.iter().map(|p| p[axis] as f32).collect()));
+            }
+        };
+        add_color("", ["R", "G", "B"], &self.beauty);
+        add_color("emission", ["R", "G", "B"], &self.emission);
+        add_color("direct", ["R", "G", "B"], &self.direct);
+        add_color("indirect", ["R", "G", "B"], &self.indirect);
+        add_color("albedo", ["R", "G", "B"], &self.albedo);
+        add_color("normal", ["X", "Y", "Z"], &self.normal);
+        channels.push(("Z".to_string(), self.depth.iter().map(|&d| d as f32).collect()));
+        channels.push(("object.id".to_string(), self.object_id.iter().map(|&id| id as f32).collect()));
+        channels.push(("material.id".to_string(), self.material_id.iter().map(|&id| id as f32).collect()));
+
+        let channels: Vec<(&str, &[f32])> = channels.iter().map(|(name, values)| (name.as_str(), values.as_slice())).collect();
+        exr::write_cropped(path, self.frame, self.origin, self.width, self.height, &channels)
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    objects::{first_unmasked, Aabb, Hittable, HitRecord, Hittables},
+    simd::{Floats, RayPacket, LANES},
+    vec::{Float, Point, Ray, Vec3},
+};
+use std::{cmp::Ordering, ops::Range};
+
+// Leaves with this many objects or fewer are not split further
+const MAX_LEAF_ITEMS: usize = 2;
+// Nodes waiting to be visited, which is plenty for trees built by halving as they are balanced
+const STACK_SIZE: usize = 64;
+
+enum NodeKind {
+    /// A run of `count` objects starting at `first`
+    Leaf { first: usize, count: usize },
+    /// The first child follows the node, and the second is at `second`.
+    /// The children were split along `axis`, with the first holding the lower half.
+    Interior { second: usize, axis: usize },
+}
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// One of the objects the tree was built from, numbered from 1 in the order they were added
+struct Item {
+    id: u32,
+    object: Box<dyn Hittable + Send + Sync>,
+}
+
+impl Item {
+    /// The closest hit the object's opacity lets through, tagged with the object's number
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        first_unmasked(self.object.as_ref(), ray, tmin, tmax).map(|hit| hit.with_object_id(self.id))
+    }
+}
+
+/// The nodes of a bounding volume hierarchy, apart from what its leaves hold,
+/// so that the same tree serves a scene's objects and the faces of a mesh
+pub(crate) struct Tree {
+    // Depth first, so that a node's first child comes straight after it
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// Builds a tree over `entries`, reordering them so that each leaf's entries are next to each other
+    pub(crate) fn new<T>(entries: &mut [(Aabb, T)]) -> Self {
+        let mut nodes = vec![];
+        if !entries.is_empty() {
+            build(&mut nodes, entries, 0);
+        }
+        Self { nodes }
+    }
+
+    /// Visits the leaves whose bounds the ray passes through between `tmin` and `tmax`, roughly nearest first.
+    /// `visit` is given the range of each leaf's entries and may bring `tmax` closer, and returns whether to stop.
+    pub(crate) fn traverse(&self, ray: &Ray, tmin: Float, mut tmax: Float, mut visit: impl FnMut(Range<usize>, &mut Float) -> bool) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let dir = ray.direction();
+        let inv = Vec3(1.0 / dir.0, 1.0 / dir.1, 1.0 / dir.2);
+        let mut stack = [0; STACK_SIZE];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let index = stack[len];
+            let node = &self.nodes[index];
+            if !overlaps(&node.bounds, ray.origin(), &inv, tmin, tmax) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    if visit(first..first + count, &mut tmax) {
+                        return;
+                    }
+                },
+                NodeKind::Interior { second, axis } => {
+                    // The nearer child goes on top so that it is visited first, and hits there cut the other short
+                    let (near, far) = if inv[axis] < 0.0 { (second, index + 1) } else { (index + 1, second) };
+                    stack[len] = far;
+                    stack[len + 1] = near;
+                    len += 2;
+                }
+            }
+        }
+    }
+
+    /// Bounds around every entry, or `None` for a tree without any
+    pub(crate) fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.bounds)
+    }
+}
+
+/// A bounding volume hierarchy over a scene's objects, so that rays only test the objects near them
+pub struct Bvh {
+    tree: Tree,
+    // Ordered so that each leaf's objects are next to each other
+    items: Vec<Item>,
+    // Objects without bounds, which every ray has to test
+    unbounded: Vec<Item>,
+}
+
+impl Bvh {
+    pub fn new(objects: Hittables) -> Self {
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        for (index, object) in objects.into_items().into_iter().enumerate() {
+            let item = Item { id: index as u32 + 1, object };
+            match item.object.bounding_box() {
+                Some(bounds) => bounded.push((bounds, item)),
+                None => unbounded.push(item)
+            }
+        }
+
+        Self {
+            tree: Tree::new(&mut bounded),
+            items: bounded.into_iter().map(|(_, item)| item).collect(),
+            unbounded
+        }
+    }
+
+    /// Visits the leaves whose bounds the ray passes through between `tmin` and `tmax`, roughly nearest first.
+    /// `visit` is given each leaf's objects and may bring `tmax` closer, and returns whether to stop.
+    fn traverse<'a>(&'a self, ray: &Ray, tmin: Float, tmax: Float, mut visit: impl FnMut(&'a [Item], &mut Float) -> bool) {
+        self.tree.traverse(ray, tmin, tmax, |range, tmax| visit(&self.items[range], tmax));
+    }
+
+    /// Finds the closest hit for each of up to `LANES` rays, testing boxes against the whole packet at once with vector instructions.
+    /// Objects in the leaves are still tested one ray at a time, for each ray whose lane reached the leaf.
+    /// Works best when the rays are coherent, such as camera rays through the same pixel.
+    pub fn hit_packet(&self, rays: &[Ray], tmin: Float, tmax: Float) -> [Option<HitRecord<'_>>; LANES] {
+        let mut records: [Option<HitRecord<'_>>; LANES] = std::array::from_fn(|_| None);
+        let mut closest = Floats::splat(tmax);
+        for (lane, ray) in rays.iter().enumerate() {
+            for item in self.unbounded.iter() {
+                if let Some(hit) = item.hit(ray, tmin, closest.lane(lane)) {
+                    closest.set_lane(lane, hit.t);
+                    records[lane] = Some(hit);
+                }
+            }
+        }
+        if self.tree.nodes.is_empty() {
+            return records;
+        }
+
+        let packet = RayPacket::new(rays);
+        let active = packet.active();
+        let tmins = Floats::splat(tmin);
+        let mut stack = [0; STACK_SIZE];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let index = stack[len];
+            let node = &self.tree.nodes[index];
+            let mask = packet.overlaps(&node.bounds.min, &node.bounds.max, tmins, closest).and(active);
+            if !mask.any() {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for (lane, ray) in rays.iter().enumerate().filter(|&(lane, _)| mask.lane(lane)) {
+                        for item in self.items[first..first + count].iter() {
+                            if let Some(hit) = item.hit(ray, tmin, closest.lane(lane)) {
+                                closest.set_lane(lane, hit.t);
+                                records[lane] = Some(hit);
+                            }
+                        }
+                    }
+                },
+                NodeKind::Interior { second, axis } => {
+                    let (near, far) = if packet.is_negative(axis, mask) { (second, index + 1) } else { (index + 1, second) };
+                    stack[len] = far;
+                    stack[len + 1] = near;
+                    len += 2;
+                }
+            }
+        }
+        records
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let mut record: Option<HitRecord<'_>> = None;
+        let mut closest = tmax;
+        for item in self.unbounded.iter() {
+            if let Some(hit) = item.hit(ray, tmin, closest) {
+                closest = hit.t;
+                record = Some(hit);
+            }
+        }
+
+        self.traverse(ray, tmin, closest, |items, closest| {
+            for item in items {
+                if let Some(hit) = item.hit(ray, tmin, *closest) {
+                    *closest = hit.t;
+                    record = Some(hit);
+                }
+            }
+            false
+        });
+        record
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.unbounded.iter().any(|item| item.object.occluded(ray, tmin, tmax)) {
+            return true;
+        }
+        let mut found = false;
+        self.traverse(ray, tmin, tmax, |items, _| {
+            found = items.iter().any(|item| item.object.occluded(ray, tmin, tmax));
+            found
+        });
+        found
+    }
+
+    fn transmittance(&self, ray: &Ray, tmin: Float, tmax: Float) -> Float {
+        let mut transmittance: Float = self.unbounded.iter().map(|item| item.object.transmittance(ray, tmin, tmax)).product();
+        if transmittance <= 0.0 {
+            return 0.0;
+        }
+        self.traverse(ray, tmin, tmax, |items, _| {
+            for item in items {
+                transmittance *= item.object.transmittance(ray, tmin, tmax);
+                if transmittance <= 0.0 {
+                    return true;
+                }
+            }
+            false
+        });
+        transmittance
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.tree.bounds()
+        } else {
+            None
+        }
+    }
+}
+
+/// Adds the nodes for `entries` in depth first order, splitting them in half along the axis their centers spread the most on
+fn build<T>(nodes: &mut Vec<Node>, entries: &mut [(Aabb, T)], first: usize) {
+    let bounds = entries[1..].iter().fold(entries[0].0, |bounds, (b, _)| bounds.union(b));
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds,
+        kind: NodeKind::Leaf { first, count: entries.len() }
+    });
+    if entries.len() <= MAX_LEAF_ITEMS {
+        return;
+    }
+
+    let centroids: Vec<Point> = entries.iter().map(|(b, _)| b.centroid()).collect();
+    let spread = Aabb::around(&centroids);
+    let extent = spread.max - spread.min;
+    let axis = if extent.0 >= extent.1 && extent.0 >= extent.2 { 0 } else if extent.1 >= extent.2 { 1 } else { 2 };
+    if extent[axis] <= 0.0 {
+        // Every center is in the same place, so splitting would not help
+        return;
+    }
+
+    let mid = entries.len() / 2;
+    entries.select_nth_unstable_by(mid, |(a, _), (b, _)| a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap_or(Ordering::Equal));
+    let (lower, upper) = entries.split_at_mut(mid);
+    build(nodes, lower, first);
+    let second = nodes.len();
+    build(nodes, upper, first + mid);
+    nodes[index].kind = NodeKind::Interior { second, axis };
+}
+
+/// Slab test with the ray's inverse direction worked out ahead of time
+fn overlaps(bounds: &Aabb, origin: &Point, inv: &Vec3, tmin: Float, tmax: Float) -> bool {
+    let mut enter = tmin;
+    let mut exit = tmax;
+    for axis in 0..3 {
+        let t0 = (bounds.min[axis] - origin[axis]) * inv[axis];
+        let t1 = (bounds.max[axis] - origin[axis]) * inv[axis];
+        // Rays parallel to a flat box give NaN here, which min and max skip over
+        enter = enter.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+    }
+    enter <= exit
+}
@@ -1,4 +1,4 @@
-use crate::{vec::{Ray, Vec3}, ASPECT_RATIO, Point, util::*};
+use crate::{vec::{Float, Point, Ray, RayDifferential, Vec3}, util::*};
 
 #[derive(Debug)]
 pub struct Camera {
@@ -8,16 +8,18 @@ pub struct Camera {
     lower_left: Point,
     horizontal: Vec3,
     vertical: Vec3,
-    fov: f64,
+    fov: Float,
+    aspect_ratio: Float,
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    lens_radius: f64,
-    focus_dist: f64
+    lens_radius: Float,
+    focus_dist: Float
 }
 
 impl Camera {
-    pub fn new(origin: Point, target: Point, up: Vec3, fov: f64, apeture: f64, focus_dist: f64) -> Self {
+    /// `aspect_ratio` is the width of the image over its height
+    pub fn new(origin: Point, target: Point, up: Vec3, fov: Float, aspect_ratio: Float, apeture: Float, focus_dist: Float) -> Self {
         assert_ne!(origin, target, "Must not face the origin point");
         assert!(fov.abs() < 90.0, "Field of view must be less than 90 degrees");
 
@@ -25,25 +27,26 @@ impl Camera {
 
         let h = fov.to_radians().tan();
         let viewport_height = 2.0 * h;
-        let viewport_width = ASPECT_RATIO * viewport_height;
+        let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (&origin - &target).normalize();
+        let w = (origin - target).normalize();
         let u = up.cross(&w).normalize();
         let v = w.cross(&u);
 
-        let horizontal = focus_dist * viewport_width * &u;
-        let vertical = focus_dist * viewport_height * &v;
-        let lower_left = &origin - &horizontal/2.0 - &vertical/2.0 - focus_dist * &w;
+        let horizontal = focus_dist * viewport_width * u;
+        let vertical = focus_dist * viewport_height * v;
+        let lower_left = origin - &horizontal/2.0 - &vertical/2.0 - focus_dist * w;
         let lens_radius = apeture/2.0;
         
         Self {
-            origin: origin.clone(),
+            origin,
             lower_left,
             horizontal,
             vertical,
             target,
             up,
             fov,
+            aspect_ratio,
             u,
             v,
             w,
@@ -52,10 +55,23 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
+    pub fn get_ray(&self, u: Float, v: Float) -> Ray {
         let rd = self.lens_radius * random_disk_vec(1.0);
-        let offset = rd.0 * &self.u + rd.1 * &self.v;
-        Ray::new(&(&self.origin + &offset), &(&self.lower_left + u*&self.horizontal + v*&self.vertical - &self.origin - offset))
+        let offset = rd.0 * self.u + rd.1 * self.v;
+        Ray::new(&(self.origin + offset), &(self.lower_left + u*self.horizontal + v*self.vertical - self.origin - offset))
+    }
+
+    /// Like `get_ray`, along with the rays `du` further across and `dv` further up the image through the same point on the lens
+    pub fn get_ray_differential(&self, u: Float, v: Float, du: Float, dv: Float) -> Ray {
+        let rd = self.lens_radius * random_disk_vec(1.0);
+        let origin = self.origin + rd.0 * self.u + rd.1 * self.v;
+        let direction = |u: Float, v: Float| self.lower_left + u*self.horizontal + v*self.vertical - origin;
+        Ray::new(&origin, &direction(u, v)).with_differential(Some(RayDifferential {
+            x_origin: origin,
+            x_direction: direction(u + du, v),
+            y_origin: origin,
+            y_direction: direction(u, v + dv)
+        }))
     }
 
     pub fn set_facing(&mut self, target: Point) {
@@ -63,14 +79,14 @@ impl Camera {
 
         let h = self.fov.to_radians().tan();
         let viewport_height = 2.0 * h;
-        let viewport_width = ASPECT_RATIO * viewport_height;
+        let viewport_width = self.aspect_ratio * viewport_height;
 
-        let w = (&self.origin - &target).normalize();
+        let w = (self.origin - target).normalize();
         let u = self.up.cross(&w);
         let v = w.cross(&u);
         let horizontal = viewport_width * u;
         let vertical = viewport_height * v;
-        let lower_left = &self.origin - &horizontal/2.0 - &vertical/2.0 - self.focus_dist * w;
+        let lower_left = self.origin - &horizontal/2.0 - &vertical/2.0 - self.focus_dist * w;
 
         self.lower_left = lower_left;
         self.horizontal = horizontal;
@@ -83,14 +99,14 @@ impl Camera {
 
         let h = self.fov.to_radians().tan();
         let viewport_height = 2.0 * h;
-        let viewport_width = ASPECT_RATIO * viewport_height;
+        let viewport_width = self.aspect_ratio * viewport_height;
 
-        let w = (&origin - &self.target).normalize();
+        let w = (origin - self.target).normalize();
         let u = self.up.cross(&w).normalize();
         let v = w.cross(&u);
-        let horizontal = self.focus_dist * viewport_width * &u;
-        let vertical = self.focus_dist * viewport_height * &v;
-        let lower_left = &self.origin - &horizontal/2.0 - &vertical/2.0 - self.focus_dist * &w;
+        let horizontal = self.focus_dist * viewport_width * u;
+        let vertical = self.focus_dist * viewport_height * v;
+        let lower_left = self.origin - &horizontal/2.0 - &vertical/2.0 - self.focus_dist * w;
 
         self.lower_left = lower_left;
         self.horizontal = horizontal;
@@ -0,0 +1,202 @@
+use crate::{Ray, objects::{Aabb, Hittable, HitRecord, Interval}, vec::Float};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOp {
+    /// Inside either object
+    Union,
+    /// Inside both objects
+    Intersection,
+    /// Inside the left object but not the right
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry combining two closed objects.
+/// Surfaces keep the material of the object they came from.
+pub struct Csg {
+    op: CsgOp,
+    left: Box<dyn Hittable + Send + Sync>,
+    right: Box<dyn Hittable + Send + Sync>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Box<dyn Hittable + Send + Sync>, right: Box<dyn Hittable + Send + Sync>) -> Self {
+        // Open surfaces have no inside, so combining them would silently leave nothing
+        assert!(left.is_closed() && right.is_closed(), "CSG needs closed objects, such as spheres, cuboids or closed meshes");
+        Self {
+            op,
+            left,
+            right
+        }
+    }
+
+    pub fn union(left: Box<dyn Hittable + Send + Sync>, right: Box<dyn Hittable + Send + Sync>) -> Self {
+        Self::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hittable + Send + Sync>, right: Box<dyn Hittable + Send + Sync>) -> Self {
+        Self::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hittable + Send + Sync>, right: Box<dyn Hittable + Send + Sync>) -> Self {
+        Self::new(CsgOp::Difference, left, right)
+    }
+}
+
+struct Boundary<'a> {
+    is_left: bool,
+    entering: bool,
+    record: HitRecord<'a>,
+}
+
+impl<'a> Boundary<'a> {
+    /// Both ends of each interval, from the left or right side of the operation
+    fn from_intervals(intervals: Vec<Interval<'a>>, is_left: bool) -> impl Iterator<Item = Self> {
+        intervals.into_iter().flat_map(move |interval| vec![
+            Boundary { is_left, entering: true, record: interval.enter },
+            Boundary { is_left, entering: false, record: interval.exit },
+        ])
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        self.intervals(ray).into_iter()
+            .flat_map(|interval| vec![interval.enter, interval.exit])
+            .find(|record| tmin < record.t && record.t < tmax)
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let mut boundaries: Vec<Boundary> = Boundary::from_intervals(self.left.intervals(ray), true)
+            .chain(Boundary::from_intervals(self.right.intervals(ray), false))
+            .collect();
+        boundaries.sort_by(|a, b| a.record.t.partial_cmp(&b.record.t).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut intervals = vec![];
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut enter = None;
+        for boundary in boundaries {
+            let was_inside = self.op.contains(in_left, in_right);
+            if boundary.is_left {
+                in_left = boundary.entering;
+            } else {
+                in_right = boundary.entering;
+            }
+            let is_inside = self.op.contains(in_left, in_right);
+
+            if was_inside != is_inside {
+                // Leaving the right object of a difference enters the result, so the side
+                // has to come from the result rather than the object that was crossed
+                let mut record = boundary.record;
+                record.is_outside = is_inside;
+                if is_inside {
+                    enter = Some(record);
+                } else if let Some(enter) = enter.take() {
+                    intervals.push(Interval {
+                        enter,
+                        exit: record
+                    });
+                }
+            }
+        }
+
+        intervals
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.op {
+            CsgOp::Union => Some(self.left.bounding_box()?.union(&self.right.bounding_box()?)),
+            // Neither can reach outside of the left object
+            CsgOp::Intersection | CsgOp::Difference => self.left.bounding_box(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{materials::Lambertian, objects::Sphere, vec::Vec3};
+    use std::sync::Arc;
+
+    /// Two unit spheres a radius apart along X, combined with `op`
+    fn overlapping(op: CsgOp) -> Csg {
+        let material = Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        Csg::new(op, Box::new(Sphere::new(Vec3(0.0, 0.0, 0.0), 1.0, material.clone())), Box::new(Sphere::new(Vec3(1.0, 0.0, 0.0), 1.0, material)))
+    }
+
+    /// Along X through both centers, crossing the left sphere at 2 and 4 and the right at 3 and 5
+    fn along_x() -> Ray {
+        Ray::new(&Vec3(-3.0, 0.0, 0.0), &Vec3(1.0, 0.0, 0.0))
+    }
+
+    /// The distance and side of both ends of each interval
+    fn ends(csg: &Csg, ray: &Ray) -> Vec<((Float, bool), (Float, bool))> {
+        csg.intervals(ray).iter().map(|interval| {
+            // Whichever object they came from, normals face back along the ray
+            assert!(interval.enter.normal().dot(ray.direction()) < 0.0, "Normals should face against the ray");
+            assert!(interval.exit.normal().dot(ray.direction()) < 0.0, "Normals should face against the ray");
+            ((interval.enter.t, interval.enter.is_outside), (interval.exit.t, interval.exit.is_outside))
+        }).collect()
+    }
+
+    #[test]
+    fn union_spans_both_spheres() {
+        let csg = overlapping(CsgOp::Union);
+        assert_eq!(ends(&csg, &along_x()), vec![((2.0, true), (5.0, false))]);
+
+        let hit = csg.hit(&along_x(), 2.5, Float::INFINITY).unwrap();
+        assert_eq!((hit.t, hit.is_outside), (5.0, false));
+    }
+
+    #[test]
+    fn intersection_spans_the_overlap() {
+        let csg = overlapping(CsgOp::Intersection);
+        assert_eq!(ends(&csg, &along_x()), vec![((3.0, true), (4.0, false))]);
+
+        let hit = csg.hit(&along_x(), 0.0, Float::INFINITY).unwrap();
+        assert_eq!((hit.t, hit.is_outside), (3.0, true));
+    }
+
+    #[test]
+    fn difference_flips_the_side_of_the_right_sphere() {
+        let csg = overlapping(CsgOp::Difference);
+        // Entering the right sphere leaves the result
+        assert_eq!(ends(&csg, &along_x()), vec![((2.0, true), (3.0, false))]);
+        let hit = csg.hit(&along_x(), 2.5, Float::INFINITY).unwrap();
+        assert_eq!((hit.t, hit.is_outside), (3.0, false));
+
+        // and coming the other way, leaving it enters the result
+        let back = Ray::new(&Vec3(5.0, 0.0, 0.0), &Vec3(-1.0, 0.0, 0.0));
+        assert_eq!(ends(&csg, &back), vec![((5.0, true), (6.0, false))]);
+        let hit = csg.hit(&back, 0.0, Float::INFINITY).unwrap();
+        assert_eq!((hit.t, hit.is_outside), (5.0, true));
+    }
+
+    #[test]
+    fn misses_leave_nothing() {
+        let ray = Ray::new(&Vec3(-3.0, 2.0, 0.0), &Vec3(1.0, 0.0, 0.0));
+        for op in [CsgOp::Union, CsgOp::Intersection, CsgOp::Difference] {
+            let csg = overlapping(op);
+            assert!(csg.intervals(&ray).is_empty());
+            assert!(csg.hit(&ray, 0.0, Float::INFINITY).is_none());
+        }
+        // Only through the left sphere, so there is no overlap
+        let left_only = Ray::new(&Vec3(-0.5, -3.0, 0.0), &Vec3(0.0, 1.0, 0.0));
+        assert!(overlapping(CsgOp::Intersection).intervals(&left_only).is_empty());
+        assert_eq!(ends(&overlapping(CsgOp::Difference), &left_only).len(), 1);
+    }
+}
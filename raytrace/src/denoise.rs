@@ -0,0 +1,138 @@
+use crate::{aov::Passes, util::luminance, vec::{Color, Float, Vec3}};
+use rayon::prelude::*;
+
+// Weights of the B3 spline, which each pass spreads out twice as far as the last
+const KERNEL: [Float; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// Albedo below this is too dark to divide lighting by without blowing up its noise
+const MIN_ALBEDO: Float = 0.01;
+
+/// Smooths out noise with a joint bilateral filter, guided by the albedo, normal and depth passes so that edges and texture stay sharp.
+/// Follows the edge avoiding À-Trous wavelet filter of Dammertz et al. 2010,
+/// applied to the lighting with the albedo divided out, and with light the camera sees directly left alone.
+pub struct Denoiser {
+    iterations: usize,
+    color_sigma: Float,
+    normal_sigma: Float,
+    depth_sigma: Float,
+    albedo_sigma: Float,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Self {
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.2,
+            depth_sigma: 0.02,
+            albedo_sigma: 0.1
+        }
+    }
+
+    /// Number of passes, each reaching twice as far, so the filter covers about `4 * 2^iterations` pixels across
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        assert!(iterations > 0, "The denoiser needs at least one iteration");
+        self.iterations = iterations;
+        self
+    }
+
+    /// How different two pixels' lighting can be before they stop being blended, relative to how bright they are.
+    /// Higher values remove more noise and also more detail.
+    pub fn with_color_sigma(mut self, sigma: Float) -> Self {
+        assert!(sigma > 0.0, "Sigmas must be positive");
+        self.color_sigma = sigma;
+        self
+    }
+
+    /// How far apart two unit normals can be before the surfaces count as different
+    pub fn with_normal_sigma(mut self, sigma: Float) -> Self {
+        assert!(sigma > 0.0, "Sigmas must be positive");
+        self.normal_sigma = sigma;
+        self
+    }
+
+    /// How much depth can change from one pixel to the next, relative to the depth, before it counts as an edge
+    pub fn with_depth_sigma(mut self, sigma: Float) -> Self {
+        assert!(sigma > 0.0, "Sigmas must be positive");
+        self.depth_sigma = sigma;
+        self
+    }
+
+    pub fn with_albedo_sigma(mut self, sigma: Float) -> Self {
+        assert!(sigma > 0.0, "Sigmas must be positive");
+        self.albedo_sigma = sigma;
+        self
+    }
+
+    /// The beauty pass with its noise smoothed out, in the same layout
+    pub fn denoise(&self, passes: &Passes) -> Vec<Color> {
+        let albedo = passes.albedo();
+        // Texture is divided out so that the filter only blurs lighting, and multiplied back in afterwards
+        let demodulate = |a: Float| if a > MIN_ALBEDO { a } else { 1.0 };
+        let factors: Vec<Color> = albedo.iter().map(|a| Vec3(demodulate(a.0), demodulate(a.1), demodulate(a.2))).collect();
+        let mut lighting: Vec<Color> = passes.beauty().iter().zip(passes.emission()).zip(&factors)
+            .map(|((beauty, emission), factor)| {
+                let l = *beauty - *emission;
+                Vec3(l.0 / factor.0, l.1 / factor.1, l.2 / factor.2)
+            })
+            .collect();
+
+        for iteration in 0..self.iterations {
+            // Later passes compare lighting that is already smoother, so they can be stricter about it
+            let color_sigma = self.color_sigma / (1 << iteration) as Float;
+            lighting = self.filter(passes, &lighting, 1 << iteration, color_sigma);
+        }
+
+        lighting.iter().zip(&factors).zip(passes.emission()).map(|((l, factor), emission)| *l * *factor + *emission).collect()
+    }
+
+    /// One pass of the filter, taking every `step`th pixel in a 5×5 grid around each pixel
+    fn filter(&self, passes: &Passes, lighting: &[Color], step: usize, color_sigma: Float) -> Vec<Color> {
+        let (width, height) = (passes.width(), passes.height());
+        let (albedo, normal, depth) = (passes.albedo(), passes.normal(), passes.depth());
+        (0..width * height).into_par_iter().map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let mut sum = Vec3(0.0, 0.0, 0.0);
+            let mut total = 0.0;
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let qx = x + (dx as isize - 2) * step as isize;
+                    let qy = y + (dy as isize - 2) * step as isize;
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let j = qy as usize * width + qx as usize;
+                    let pixels = step as Float * ((dx as Float - 2.0).powi(2) + (dy as Float - 2.0).powi(2)).sqrt();
+
+                    let depth_weight = match (depth[i].is_finite(), depth[j].is_finite()) {
+                        // Pixels that only see the environment are all alike
+                        (false, false) => 1.0,
+                        (true, true) => {
+                            let change = (depth[i] - depth[j]).abs() / depth[i].max(1e-6);
+                            (-change / (self.depth_sigma * pixels.max(1.0))).exp()
+                        },
+                        _ => 0.0
+                    };
+                    if depth_weight <= 0.0 {
+                        continue;
+                    }
+                    let normal_weight = (-(normal[i] - normal[j]).length_squared() / self.normal_sigma.powi(2)).exp();
+                    let albedo_weight = (-(albedo[i] - albedo[j]).length_squared() / self.albedo_sigma.powi(2)).exp();
+                    let color_change = luminance(&(lighting[i] - lighting[j])).abs() / (luminance(&lighting[i]).abs() + luminance(&lighting[j]).abs() + 1e-3);
+                    let color_weight = (-color_change.powi(2) / color_sigma.powi(2)).exp();
+
+                    let weight = kx * ky * depth_weight * normal_weight * albedo_weight * color_weight;
+                    sum = sum + weight * lighting[j];
+                    total += weight;
+                }
+            }
+            // The pixel itself always has a weight, so the total is never zero
+            (1.0 / total) * sum
+        }).collect()
+    }
+}
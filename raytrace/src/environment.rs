@@ -0,0 +1,206 @@
+use crate::{exr, util::*, vec::{consts::PI, Color, Float, Vec3}};
+use image::codecs::hdr::HdrDecoder;
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Light arriving from infinitely far away, seen by rays that escape the scene
+pub trait Environment {
+    /// Light arriving from the direction a ray escaped in
+    fn radiance(&self, direction: &Vec3) -> Color;
+
+    /// Chooses a direction to gather light from, from two uniform random numbers, with its density over solid angle
+    fn sample(&self, u: (Float, Float)) -> (Vec3, Float);
+
+    /// Density with respect to solid angle of `sample` choosing a direction
+    fn pdf(&self, direction: &Vec3) -> Float;
+}
+
+/// A sky fading from one color at the horizon to another overhead
+pub struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl Gradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self {
+            bottom,
+            top
+        }
+    }
+}
+
+impl Environment for Gradient {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let t = 0.5 * (direction.normalize().1 + 1.0);
+        lerp(self.bottom, self.top, t)
+    }
+
+    fn sample(&self, u: (Float, Float)) -> (Vec3, Float) {
+        (uniform_sphere(u), 1.0 / (4.0 * PI))
+    }
+
+    fn pdf(&self, _direction: &Vec3) -> Float {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// A piecewise constant distribution over [0, 1), for picking values in proportion to a function
+struct Distribution {
+    // Running totals, normalized so that the last is 1
+    cdf: Vec<Float>,
+    // The integral of the function over [0, 1)
+    integral: Float,
+}
+
+impl Distribution {
+    fn new(weights: &[Float]) -> Self {
+        let total: Float = weights.iter().sum();
+        let n = weights.len() as Float;
+        let mut running = 0.0;
+        let cdf = weights.iter().enumerate().map(|(i, w)| {
+            running += w;
+            // Functions that are zero everywhere are sampled uniformly
+            if total > 0.0 { running / total } else { (i + 1) as Float / n }
+        }).collect();
+        Self {
+            cdf,
+            integral: total / n
+        }
+    }
+
+    /// The bucket `u` falls in, and where in [0, 1) it lands
+    fn sample(&self, u: Float) -> (usize, Float) {
+        let i = self.cdf.partition_point(|&c| c <= u).min(self.cdf.len() - 1);
+        let start = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        let width = self.cdf[i] - start;
+        let offset = if width > 0.0 { (u - start) / width } else { 0.5 };
+        (i, (i as Float + offset) / self.cdf.len() as Float)
+    }
+
+    fn pdf(&self, i: usize) -> Float {
+        let start = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        (self.cdf[i] - start) * self.cdf.len() as Float
+    }
+}
+
+/// An equirectangular image of the surroundings, with +Y at the top row and -Z in the middle
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    // Linear, row by row from the top
+    pixels: Vec<Color>,
+    // Around +Y, in radians
+    rotation: Float,
+    intensity: Float,
+    // Picks rows, and then a column within the row, by brightness
+    rows: Distribution,
+    columns: Vec<Distribution>,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "Environment maps must not be empty");
+        assert_eq!(pixels.len(), width * height, "Environment maps must have one pixel per texel");
+
+        // Rows near the poles cover less of the sphere, so they are weighed down to match
+        let columns: Vec<Distribution> = pixels.chunks_exact(width).enumerate().map(|(y, row)| {
+            let sin_theta = (PI * (y as Float + 0.5) / height as Float).sin();
+            let weights: Vec<Float> = row.iter().map(|c| sin_theta * luminance(c).max(0.0)).collect();
+            Distribution::new(&weights)
+        }).collect();
+        let rows = Distribution::new(&columns.iter().map(|c| c.integral).collect::<Vec<Float>>());
+
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            rows,
+            columns
+        }
+    }
+
+    /// Loads a Radiance `.hdr` or OpenEXR `.exr` image, or an 8 bit image which is converted from sRGB
+    pub fn load(path: impl AsRef<Path>) -> image::ImageResult<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let metadata = decoder.metadata();
+                let pixels = decoder.read_image_hdr()?.iter().map(|p| Vec3(p[0] as Float, p[1] as Float, p[2] as Float)).collect();
+                Ok(Self::new(metadata.width as usize, metadata.height as usize, pixels))
+            },
+            Some("exr") => {
+                let (width, height, pixels) = exr::read_rgb(path)?;
+                Ok(Self::new(width, height, pixels))
+            },
+            _ => {
+                let image = image::open(path)?.to_rgb8();
+                let to_linear = |c: u8| srgb_to_linear(c as Float / 255.0);
+                let pixels = image.pixels().map(|p| Vec3(to_linear(p[0]), to_linear(p[1]), to_linear(p[2]))).collect();
+                Ok(Self::new(image.width() as usize, image.height() as usize, pixels))
+            }
+        }
+    }
+
+    /// Turns the surroundings counterclockwise around +Y when seen from above
+    pub fn with_rotation(mut self, degrees: Float) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    /// Scales the brightness of the whole map
+    pub fn with_intensity(mut self, intensity: Float) -> Self {
+        assert!(intensity >= 0.0, "Environment intensity must not be negative");
+        self.intensity = intensity;
+        self
+    }
+
+    /// Position on the image of a direction, with both coordinates in [0, 1)
+    fn to_uv(&self, direction: &Vec3) -> (Float, Float) {
+        let d = direction.normalize();
+        let phi = (-d.2).atan2(d.0) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = d.1.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn direction_at(&self, (u, v): (Float, Float)) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Vec3(phi.cos() * theta.sin(), theta.cos(), -phi.sin() * theta.sin())
+    }
+
+    fn texel(&self, (u, v): (Float, Float)) -> (usize, usize) {
+        let x = ((u * self.width as Float) as usize).min(self.width - 1);
+        let y = ((v * self.height as Float) as usize).min(self.height - 1);
+        (x, y)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (x, y) = self.texel(self.to_uv(direction));
+        self.intensity * self.pixels[y * self.width + x]
+    }
+
+    fn sample(&self, u: (Float, Float)) -> (Vec3, Float) {
+        let (y, v) = self.rows.sample(u.1);
+        let (_, u) = self.columns[y].sample(u.0);
+        let direction = self.direction_at((u, v));
+        (direction, self.pdf(&direction))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> Float {
+        let (u, v) = self.to_uv(direction);
+        let (x, y) = self.texel((u, v));
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // The image covers 2π by π radians, squeezed together by sin θ towards the poles
+        self.rows.pdf(y) * self.columns[y].pdf(x) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,585 @@
+use crate::vec::{Color, Float, Vec3};
+use std::{fs, io, path::Path};
+
+// Version 2 of the format, with no flags set
+const VERSION: u32 = 2;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// Version flags for tiled, deep and multi part files, none of which are supported
+const UNSUPPORTED_FLAGS: u32 = 0x200 | 0x800 | 0x1000;
+// Far more than any render needs, but it keeps a corrupt data window from asking for endless memory
+const MAX_PIXELS: usize = 1 << 26;
+
+/// A channel of a scanline image, in the order its values are stored
+struct Channel {
+    name: String,
+    // 0 is u32, 1 is f16 and 2 is f32
+    pixel_type: i32,
+}
+
+impl Channel {
+    fn size(&self) -> usize {
+        if self.pixel_type == 1 { 2 } else { 4 }
+    }
+}
+
+/// Reads the linear RGB pixels of a single part scanline OpenEXR file, row by row from the top.
+/// Supports uncompressed, RLE and zip compressed files, with half or full float channels.
+pub fn read_rgb(path: impl AsRef<Path>) -> io::Result<(usize, usize, Vec<Color>)> {
+    let bytes = fs::read(path)?;
+    let mut reader = Reader { bytes: &bytes, pos: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    if reader.u32()? & UNSUPPORTED_FLAGS != 0 {
+        return Err(invalid("only single part scanline OpenEXR files are supported"));
+    }
+
+    let mut channels = vec![];
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = reader.string()?;
+        let size = reader.i32()? as usize;
+        let value = reader.take(size)?;
+        let mut value = Reader { bytes: value, pos: 0 };
+        match name.as_str() {
+            "channels" => loop {
+                let name = value.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                let pixel_type = value.i32()?;
+                // Linear flag, padding and subsampling, which must be 1 for RGB data
+                value.take(12)?;
+                channels.push(Channel { name, pixel_type });
+            },
+            "compression" => compression = Some(value.take(1)?[0]),
+            "dataWindow" => data_window = Some([value.i32()?, value.i32()?, value.i32()?, value.i32()?]),
+            _ => {}
+        }
+    }
+
+    let [x_min, y_min, x_max, y_max] = data_window.ok_or_else(|| invalid("OpenEXR file has no data window"))?;
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid("OpenEXR data window is empty"));
+    }
+    let size = |min: i32, max: i32| max.checked_sub(min)?.checked_add(1);
+    let (width, height) = match (size(x_min, x_max), size(y_min, y_max)) {
+        (Some(width), Some(height)) => (width as usize, height as usize),
+        _ => return Err(invalid("OpenEXR data window is too large"))
+    };
+    if width.checked_mul(height).is_none_or(|pixels| pixels > MAX_PIXELS) {
+        return Err(invalid("OpenEXR data window is too large"));
+    }
+    let lines_per_chunk = match compression {
+        Some(0) | Some(1) | Some(2) => 1,
+        Some(3) => 16,
+        _ => return Err(invalid("OpenEXR compression must be none, RLE or zip"))
+    };
+    let compression = compression.unwrap_or(0);
+
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err(invalid("OpenEXR file has no RGB or luminance channels"))
+    };
+    // Each line stores every value of one channel before moving on to the next
+    let mut starts = Vec::with_capacity(channels.len());
+    let mut line_size = 0;
+    for channel in channels.iter() {
+        starts.push(line_size);
+        line_size += channel.size() * width;
+    }
+
+    let chunks = height.div_ceil(lines_per_chunk);
+    let offsets = (0..chunks).map(|_| reader.u64()).collect::<io::Result<Vec<u64>>>()?;
+
+    let mut pixels = vec![Vec3(0.0, 0.0, 0.0); width * height];
+    for offset in offsets {
+        let mut chunk = Reader { bytes: &bytes, pos: offset as usize };
+        let outside = || invalid("OpenEXR chunk is outside of the data window");
+        let first_line = chunk.i32()?.checked_sub(y_min).ok_or_else(outside)?;
+        let size = chunk.i32()? as usize;
+        let lines = lines_per_chunk.min(height.saturating_sub(first_line.max(0) as usize));
+        if first_line < 0 || lines == 0 {
+            return Err(outside());
+        }
+
+        let expected = lines * line_size;
+        let data = decompress(chunk.take(size)?, compression, expected)?;
+        if data.len() != expected {
+            return Err(invalid("OpenEXR chunk has the wrong size"));
+        }
+
+        for line in 0..lines {
+            let y = first_line as usize + line;
+            let line_data = &data[line * line_size..(line + 1) * line_size];
+            for x in 0..width {
+                let value = |c: usize| read_value(&line_data[starts[c] + x * channels[c].size()..], channels[c].pixel_type);
+                pixels[y * width + x] = Vec3(value(rgb[0]) as Float, value(rgb[1]) as Float, value(rgb[2]) as Float);
+            }
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+/// Writes a single part scanline OpenEXR file of full float channels, with rows from the top and zip compressed lines.
+/// Channels are named the way compositors expect layers, such as `R` for the main image and `albedo.R` for a pass.
+pub fn write(path: impl AsRef<Path>, width: usize, height: usize, channels: &[(&str, &[f32])]) -> io::Result<()> {
+    write_cropped(path, (width, height), (0, 0), width, height, channels)
+}
+
+/// Like `write`, for pixels covering only part of a `frame` sized image, starting `origin` pixels from its top left.
+/// Compositors place the pixels where they belong in the frame, and treat the rest as empty.
+pub fn write_cropped(path: impl AsRef<Path>, frame: (usize, usize), origin: (usize, usize), width: usize, height: usize, channels: &[(&str, &[f32])]) -> io::Result<()> {
+    assert!(width > 0 && height > 0, "OpenEXR images must have at least one pixel");
+    assert!(origin.0 + width <= frame.0 && origin.1 + height <= frame.1, "Cropped pixels must lie within the frame");
+    assert!(channels.iter().all(|(_, values)| values.len() == width * height), "Every channel needs one value per pixel");
+    // Readers expect channels in alphabetical order
+    let mut channels = channels.to_vec();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut header = vec![];
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    let mut list = vec![];
+    for (name, _) in channels.iter() {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // Full float, not linear, padding and no subsampling
+        list.extend_from_slice(&2i32.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    let window = |x: usize, y: usize, width: usize, height: usize| -> Vec<u8> {
+        [x as i32, y as i32, (x + width) as i32 - 1, (y + height) as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect()
+    };
+    attribute(&mut header, "channels", "chlist", &list);
+    // Zip compression of single lines
+    attribute(&mut header, "compression", "compression", &[2]);
+    attribute(&mut header, "dataWindow", "box2i", &window(origin.0, origin.1, width, height));
+    attribute(&mut header, "displayWindow", "box2i", &window(0, 0, frame.0, frame.1));
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    let chunks: Vec<Vec<u8>> = (0..height).map(|y| {
+        let line: Vec<u8> = channels.iter()
+            .flat_map(|(_, values)| values[y * width..(y + 1) * width].iter().flat_map(|v| v.to_le_bytes()))
+            .collect();
+        let data = compress(&line);
+        let mut chunk = Vec::with_capacity(data.len() + 8);
+        chunk.extend_from_slice(&((origin.1 + y) as i32).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        chunk.extend_from_slice(&data);
+        chunk
+    }).collect();
+
+    let mut bytes = header;
+    let mut offset = (bytes.len() + 8 * height) as u64;
+    for chunk in chunks.iter() {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        offset += chunk.len() as u64;
+    }
+    for chunk in chunks.iter() {
+        bytes.extend_from_slice(chunk);
+    }
+    fs::write(path, bytes)
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for s in [name, kind] {
+        header.extend_from_slice(s.as_bytes());
+        header.push(0);
+    }
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// The reverse of `decompress` for zip compression, keeping chunks as they are when that would not make them smaller
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = data.iter().step_by(2).chain(data.iter().skip(1).step_by(2)).copied().collect();
+    for i in (1..bytes.len()).rev() {
+        bytes[i] = bytes[i].wrapping_sub(bytes[i - 1]).wrapping_add(128);
+    }
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&bytes, 6);
+    if compressed.len() < data.len() { compressed } else { data.to_vec() }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_value(bytes: &[u8], pixel_type: i32) -> f64 {
+    match pixel_type {
+        0 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        1 => half_to_f64(u16::from_le_bytes([bytes[0], bytes[1]])),
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    }
+}
+
+fn half_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    match exponent {
+        0 => sign * mantissa * 2f64.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+/// Undoes the compression of a chunk that is `expected` bytes once uncompressed
+fn decompress(data: &[u8], compression: u8, expected: usize) -> io::Result<Vec<u8>> {
+    // Chunks that would not get smaller are stored as is
+    if compression == 0 || data.len() >= expected {
+        return Ok(data.to_vec());
+    }
+    let mut bytes = if compression == 1 {
+        run_length_decode(data)?
+    } else {
+        miniz_oxide::inflate::decompress_to_vec_zlib(data).map_err(|_| invalid("OpenEXR chunk is not valid zlib data"))?
+    };
+
+    // Bytes are stored as differences from the previous byte, with the two halves of each value split apart
+    for i in 1..bytes.len() {
+        bytes[i] = bytes[i - 1].wrapping_add(bytes[i]).wrapping_sub(128);
+    }
+    let (first, second) = bytes.split_at(bytes.len().div_ceil(2));
+    let mut interleaved = Vec::with_capacity(bytes.len());
+    for i in 0..first.len() {
+        interleaved.push(first[i]);
+        if i < second.len() {
+            interleaved.push(second[i]);
+        }
+    }
+    Ok(interleaved)
+}
+
+fn run_length_decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let count = data[i] as i8;
+        i += 1;
+        if count < 0 {
+            let end = i + (-(count as i32)) as usize;
+            out.extend_from_slice(data.get(i..end).ok_or_else(|| invalid("OpenEXR RLE data is truncated"))?);
+            i = end;
+        } else {
+            let value = *data.get(i).ok_or_else(|| invalid("OpenEXR RLE data is truncated"))?;
+            out.extend(std::iter::repeat_n(value, count as usize + 1));
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Reads little endian values from the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + n).ok_or_else(|| invalid("OpenEXR file is truncated"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let b = self.take(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    /// A null terminated string
+    fn string(&mut self) -> io::Result<String> {
+        let rest = self.bytes.get(self.pos..).unwrap_or_default();
+        let len = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid("OpenEXR file is truncated"))?;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process};
+
+    /// A file in the temporary directory, unique to the test and process
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("raytrace-exr-{}-{}.exr", name, process::id()))
+    }
+
+    fn read_bytes(name: &str, bytes: &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+        let path = temp_path(name);
+        fs::write(&path, bytes)?;
+        let result = read_rgb(&path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    /// A scanline file with `chunks` already compressed, each starting at the line it is paired with
+    fn build(compression: u8, channels: &[(&str, i32)], width: usize, height: usize, chunks: &[(usize, Vec<u8>)]) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        let mut list = vec![];
+        for (name, pixel_type) in channels {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+            list.extend_from_slice(&pixel_type.to_le_bytes());
+            list.extend_from_slice(&[0, 0, 0, 0]);
+            list.extend_from_slice(&1i32.to_le_bytes());
+            list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        list.push(0);
+        let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+        attribute(&mut header, "channels", "chlist", &list);
+        attribute(&mut header, "compression", "compression", &[compression]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        header.push(0);
+
+        let mut bytes = header;
+        let mut offset = (bytes.len() + 8 * chunks.len()) as u64;
+        for (_, data) in chunks {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            offset += 8 + data.len() as u64;
+        }
+        for (y, data) in chunks {
+            bytes.extend_from_slice(&(*y as i32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Lines of full float RGB, stored blue first as channels are sorted by name
+    fn rgb_lines(pixels: &[Color], width: usize, lines: std::ops::Range<usize>) -> Vec<u8> {
+        lines.flat_map(|y| {
+            let row = &pixels[y * width..(y + 1) * width];
+            [2, 1, 0].iter().flat_map(move |&axis| row.iter().flat_map(move |p| (p[axis] as f32).to_le_bytes()))
+        }).collect()
+    }
+
+    /// Runs of at least three equal bytes are repeated, and everything else is copied
+    fn run_length_encode(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut literal = vec![];
+        let flush = |out: &mut Vec<u8>, literal: &mut Vec<u8>| {
+            if !literal.is_empty() {
+                out.push(-(literal.len() as i8) as u8);
+                out.append(literal);
+            }
+        };
+        let mut i = 0;
+        while i < data.len() {
+            let run = data[i..].iter().take(128).take_while(|&&b| b == data[i]).count();
+            if run >= 3 {
+                flush(&mut out, &mut literal);
+                out.push(run as u8 - 1);
+                out.push(data[i]);
+                i += run;
+            } else {
+                literal.push(data[i]);
+                i += 1;
+                if literal.len() == 127 {
+                    flush(&mut out, &mut literal);
+                }
+            }
+        }
+        flush(&mut out, &mut literal);
+        out
+    }
+
+    /// Splits and differences bytes the way RLE and zip compression expect, as `compress` does before deflating
+    fn predict(data: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = data.iter().step_by(2).chain(data.iter().skip(1).step_by(2)).copied().collect();
+        for i in (1..bytes.len()).rev() {
+            bytes[i] = bytes[i].wrapping_sub(bytes[i - 1]).wrapping_add(128);
+        }
+        bytes
+    }
+
+    fn gradient(width: usize, height: usize) -> Vec<Color> {
+        (0..width * height).map(|i| Vec3((i % width) as Float / 8.0, (i / width) as Float / 4.0, 0.5)).collect()
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let (width, height) = (7, 5);
+        let pixels = gradient(width, height);
+        let channel = |axis: usize| -> Vec<f32> { pixels.iter().map(|p| p[axis] as f32).collect() };
+        let (r, g, b) = (channel(0), channel(1), channel(2));
+        // A pass sorted between the color channels, which the reader must skip over
+        let albedo = vec![0.25; width * height];
+        let path = temp_path("write");
+        write(&path, width, height, &[("R", &r), ("G", &g), ("B", &b), ("albedo.R", &albedo)]).unwrap();
+        let read = read_rgb(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), (width, height, pixels));
+    }
+
+    /// The header attributes of a file, by name, along with where the header ends
+    fn header(bytes: &[u8]) -> (Vec<(String, Vec<u8>)>, usize) {
+        let mut reader = Reader { bytes, pos: 8 };
+        let mut attributes = vec![];
+        loop {
+            let name = reader.string().unwrap();
+            if name.is_empty() {
+                return (attributes, reader.pos);
+            }
+            reader.string().unwrap();
+            let size = reader.i32().unwrap() as usize;
+            attributes.push((name, reader.take(size).unwrap().to_vec()));
+        }
+    }
+
+    fn window(attributes: &[(String, Vec<u8>)], name: &str) -> [i32; 4] {
+        let (_, value) = attributes.iter().find(|(n, _)| n == name).unwrap();
+        let mut reader = Reader { bytes: value, pos: 0 };
+        [reader.i32().unwrap(), reader.i32().unwrap(), reader.i32().unwrap(), reader.i32().unwrap()]
+    }
+
+    #[test]
+    fn writes_cropped_pixels_where_they_belong() {
+        let (frame, origin, width, height) = ((10, 8), (3, 2), 4, 3);
+        let pixels = gradient(width, height);
+        let channel = |axis: usize| -> Vec<f32> { pixels.iter().map(|p| p[axis] as f32).collect() };
+        let (r, g, b) = (channel(0), channel(1), channel(2));
+        let path = temp_path("cropped");
+        write_cropped(&path, frame, origin, width, height, &[("R", &r), ("G", &g), ("B", &b)]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let read = read_rgb(&path);
+        fs::remove_file(&path).unwrap();
+
+        let (attributes, end) = header(&bytes);
+        assert_eq!(window(&attributes, "dataWindow"), [3, 2, 6, 4]);
+        assert_eq!(window(&attributes, "displayWindow"), [0, 0, 9, 7]);
+        // Each line's chunk is labelled with its row in the frame rather than in the crop
+        let mut offsets = Reader { bytes: &bytes, pos: end };
+        for y in 0..height {
+            let mut chunk = Reader { bytes: &bytes, pos: offsets.u64().unwrap() as usize };
+            assert_eq!(chunk.i32().unwrap(), (origin.1 + y) as i32);
+        }
+        assert_eq!(read.unwrap(), (width, height, pixels));
+    }
+
+    #[test]
+    fn reads_uncompressed_half_luminance() {
+        // 0.5, 1, 2 and -0.25 as halves
+        let values: [u16; 4] = [0x3800, 0x3c00, 0x4000, 0xb400];
+        let line = |y: usize| values[2 * y..2 * y + 2].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let bytes = build(0, &[("Y", 1)], 2, 2, &[(0, line(0)), (1, line(1))]);
+        let (width, height, pixels) = read_bytes("none", &bytes).unwrap();
+        assert_eq!((width, height), (2, 2));
+        let expected: Vec<Float> = vec![0.5, 1.0, 2.0, -0.25];
+        assert_eq!(pixels, expected.iter().map(|&y| Vec3(y, y, y)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reads_run_length_encoded() {
+        let (width, height) = (6, 3);
+        // Flat colors in each line, so that runs make the lines smaller
+        let pixels: Vec<Color> = (0..width * height).map(|i| Vec3(0.25, (i / width) as Float, 1.0)).collect();
+        let chunks: Vec<(usize, Vec<u8>)> = (0..height).map(|y| {
+            let line = rgb_lines(&pixels, width, y..y + 1);
+            let encoded = run_length_encode(&predict(&line));
+            assert!(encoded.len() < line.len(), "The test lines should compress");
+            (y, encoded)
+        }).collect();
+        let bytes = build(1, &[("B", 2), ("G", 2), ("R", 2)], width, height, &chunks);
+        assert_eq!(read_bytes("rle", &bytes).unwrap(), (width, height, pixels));
+    }
+
+    #[test]
+    fn reads_zip_compressed() {
+        // Zip compression stores 16 lines in each chunk, with the last one short
+        let (width, height) = (5, 20);
+        let pixels = gradient(width, height);
+        let chunks: Vec<(usize, Vec<u8>)> = vec![0..16, 16..20].into_iter().map(|lines| {
+            let start = lines.start;
+            (start, compress(&rgb_lines(&pixels, width, lines)))
+        }).collect();
+        let bytes = build(3, &[("B", 2), ("G", 2), ("R", 2)], width, height, &chunks);
+        assert_eq!(read_bytes("zip", &bytes).unwrap(), (width, height, pixels));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let (width, height) = (4, 3);
+        let chunks: Vec<(usize, Vec<u8>)> = (0..height).map(|y| (y, compress(&rgb_lines(&gradient(width, height), width, y..y + 1)))).collect();
+        let bytes = build(2, &[("B", 2), ("G", 2), ("R", 2)], width, height, &chunks);
+        assert!(read_bytes("whole", &bytes).is_ok());
+        for len in 0..bytes.len() {
+            assert!(read_bytes("truncated", &bytes[..len]).is_err(), "A file cut to {} bytes should not read", len);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let (width, height) = (4, 1);
+        let line = rgb_lines(&gradient(width, height), width, 0..1);
+        let mut data = compress(&line);
+        assert!(data.len() < line.len(), "The test line should compress");
+        // Breaks the zlib checksum at the end of the chunk
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let bytes = build(2, &[("B", 2), ("G", 2), ("R", 2)], width, height, &[(0, data)]);
+        assert!(read_bytes("corrupt", &bytes).is_err());
+
+        let mut bytes = build(0, &[("Y", 2)], 1, 1, &[(0, vec![0; 4])]);
+        bytes[0] = 0;
+        assert!(read_bytes("magic", &bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_data_windows() {
+        let bytes = build(0, &[("Y", 2)], 1, 1, &[(0, vec![0; 4])]);
+        let name = b"dataWindow\0box2i\0";
+        let value = bytes.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 4;
+        let with_window = |window: [i32; 4]| {
+            let mut bytes = bytes.clone();
+            for (i, v) in window.iter().enumerate() {
+                bytes[value + 4 * i..value + 4 * i + 4].copy_from_slice(&v.to_le_bytes());
+            }
+            bytes
+        };
+        assert!(read_bytes("window", &with_window([0, 0, 0, 0])).is_ok());
+
+        // Wider and taller than an i32 can count
+        assert!(read_bytes("wide", &with_window([i32::MIN, 0, i32::MAX, 0])).is_err());
+        assert!(read_bytes("tall", &with_window([0, i32::MIN, 0, i32::MAX])).is_err());
+        // Countable, but far too many pixels to allocate
+        assert!(read_bytes("huge", &with_window([0, 0, 1 << 20, 1 << 20])).is_err());
+
+        // A chunk labelled so far above the window that its line cannot be counted
+        let mut bytes = with_window([0, 1, 0, 1]);
+        let chunk = bytes.len() - 12;
+        bytes[chunk..chunk + 4].copy_from_slice(&i32::MIN.to_le_bytes());
+        assert!(read_bytes("chunk", &bytes).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    materials::{BsdfSample, Material, Metal},
+    objects::HitRecord,
+    texture::Texture,
+    util::*,
+    vec::{Color, Float, Ray, Vec3},
+};
+use std::sync::Arc;
+
+/// Blends two materials, such as dust over metal, by a weight that may vary over the surface
+pub struct Mix {
+    first: Arc<dyn Material + Send + Sync>,
+    second: Arc<dyn Material + Send + Sync>,
+    // How much of the second material there is, from 0 to 1
+    weight: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Mix {
+    pub fn new(first: Arc<dyn Material + Send + Sync>, second: Arc<dyn Material + Send + Sync>, weight: impl Texture + Send + Sync + 'static) -> Self {
+        Self {
+            first,
+            second,
+            weight: Arc::new(weight)
+        }
+    }
+
+    fn weight(&self, hit: &HitRecord) -> Float {
+        self.weight.scalar(hit).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        let w = self.weight(hit);
+        (1.0 - w) * self.first.eval(hit, wo, wi) + w * self.second.eval(hit, wo, wi)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        // Picking each material as often as it is weighted means delta lobes keep their own weight
+        let w = self.weight(hit);
+        let (second_chosen, u0) = choose(u.0, w);
+        let (chosen, chance) = if second_chosen {
+            (&self.second, w)
+        } else {
+            (&self.first, 1.0 - w)
+        };
+        let sample = chosen.sample(ray, hit, (u0, u.1))?;
+        if sample.is_delta {
+            return Some(BsdfSample {
+                pdf: chance * sample.pdf,
+                ..sample
+            });
+        }
+
+        let wo = -ray.direction().normalize();
+        let wi = sample.ray.direction().normalize();
+        let pdf = self.pdf(hit, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: (1.0 / pdf) * self.eval(hit, &wo, &wi),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let w = self.weight(hit);
+        (1.0 - w) * self.first.pdf(hit, wo, wi) + w * self.second.pdf(hit, wo, wi)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.first.is_delta() && self.second.is_delta()
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.first.samples_lights() || self.second.samples_lights()
+    }
+
+    fn walks_inside(&self) -> bool {
+        self.first.walks_inside() || self.second.walks_inside()
+    }
+
+    fn opacity(&self, hit: &HitRecord) -> Float {
+        let w = self.weight(hit);
+        (1.0 - w) * self.first.opacity(hit) + w * self.second.opacity(hit)
+    }
+
+    fn has_cutouts(&self) -> bool {
+        self.first.has_cutouts() || self.second.has_cutouts()
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        let w = self.weight(hit);
+        (1.0 - w) * self.first.albedo(hit) + w * self.second.albedo(hit)
+    }
+
+    fn emitted(&self, hit: &HitRecord, wo: &Vec3) -> Color {
+        let w = self.weight(hit);
+        (1.0 - w) * self.first.emitted(hit, wo) + w * self.second.emitted(hit, wo)
+    }
+
+    fn shading_normal(&self, hit: &HitRecord) -> Vec3 {
+        let w = self.weight(hit);
+        let normal = (1.0 - w) * self.first.shading_normal(hit) + w * self.second.shading_normal(hit);
+        // Opposite normals cancel out, which leaves nothing better than the surface's own
+        if normal.length_squared() > 0.0 { normal.normalize() } else { *hit.normal() }
+    }
+}
+
+/// A clear dielectric layer, such as varnish or lacquer, over another material.
+/// Light reaching the base is reduced by what the layer reflects on the way in and out, and by its tint.
+pub struct Coated {
+    base: Arc<dyn Material + Send + Sync>,
+    coat: Metal,
+    ior: Float,
+    // What white light becomes after passing straight through the layer and back out
+    tint: Color,
+}
+
+impl Coated {
+    /// Coats a material with a layer of the given index, smooth at a roughness of 0
+    pub fn new(base: Arc<dyn Material + Send + Sync>, ior: Float, roughness: Float) -> Self {
+        assert!(ior > 0.0, "Coatings must have a positive refractive index");
+        let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        Self {
+            base,
+            // A dielectric's reflection is a conductor's with a colorless Fresnel term
+            coat: Metal::new(Vec3(f0, f0, f0), roughness),
+            ior,
+            tint: Vec3(1.0, 1.0, 1.0)
+        }
+    }
+
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// Fraction of light passing through the layer on the way to and from the base
+    fn attenuation(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        let cos_o = wo.dot(hit.normal()).abs().max(1e-4);
+        let cos_i = wi.dot(hit.normal()).abs().max(1e-4);
+        let transmitted = (1.0 - schlick(cos_o, self.ior)) * (1.0 - schlick(cos_i, self.ior));
+        // Slanted paths travel further through the layer
+        let depth = 0.5 * (1.0 / cos_o + 1.0 / cos_i);
+        let absorbed = Vec3(self.tint.0.powf(depth), self.tint.1.powf(depth), self.tint.2.powf(depth));
+        transmitted * absorbed
+    }
+
+    /// Chance of sampling the coat rather than the base, which is how much the coat reflects towards the viewer
+    fn coat_chance(&self, hit: &HitRecord, wo: &Vec3) -> Float {
+        schlick(wo.dot(hit.normal()).clamp(0.0, 1.0), self.ior).clamp(0.05, 0.95)
+    }
+}
+
+impl Material for Coated {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.coat.eval(hit, wo, wi) + self.base.eval(hit, wo, wi) * self.attenuation(hit, wo, wi)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        let wo = -ray.direction().normalize();
+        let chance = self.coat_chance(hit, &wo);
+        let (coat_chosen, u0) = choose(u.0, chance);
+        let sample = if coat_chosen {
+            self.coat.sample(ray, hit, (u0, u.1))?
+        } else {
+            self.base.sample(ray, hit, (u0, u.1))?
+        };
+
+        if sample.is_delta {
+            let (weight, chance) = if coat_chosen {
+                (sample.weight, chance)
+            } else {
+                let wi = sample.ray.direction().normalize();
+                (sample.weight * self.attenuation(hit, &wo, &wi), 1.0 - chance)
+            };
+            return Some(BsdfSample {
+                weight: (1.0 / chance) * weight,
+                pdf: chance * sample.pdf,
+                ..sample
+            });
+        }
+
+        let wi = sample.ray.direction().normalize();
+        let pdf = self.pdf(hit, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: (1.0 / pdf) * self.eval(hit, &wo, &wi),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let chance = self.coat_chance(hit, wo);
+        chance * self.coat.pdf(hit, wo, wi) + (1.0 - chance) * self.base.pdf(hit, wo, wi)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.coat.is_delta() && self.base.is_delta()
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.coat.samples_lights() || self.base.samples_lights()
+    }
+
+    fn walks_inside(&self) -> bool {
+        self.base.walks_inside()
+    }
+
+    fn opacity(&self, hit: &HitRecord) -> Float {
+        self.base.opacity(hit)
+    }
+
+    fn has_cutouts(&self) -> bool {
+        self.base.has_cutouts()
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.base.albedo(hit) * self.tint
+    }
+
+    fn emitted(&self, hit: &HitRecord, wo: &Vec3) -> Color {
+        // Light from the base only crosses the layer once, on the way out
+        let cos = wo.dot(hit.normal()).abs().max(1e-4);
+        let depth = 0.5 / cos;
+        let absorbed = Vec3(self.tint.0.powf(depth), self.tint.1.powf(depth), self.tint.2.powf(depth));
+        (1.0 - schlick(cos, self.ior)) * (self.base.emitted(hit, wo) * absorbed)
+    }
+
+    fn shading_normal(&self, hit: &HitRecord) -> Vec3 {
+        self.base.shading_normal(hit)
+    }
+}
@@ -0,0 +1,29 @@
+// Constants are written out to double precision, and rounded when built for single.
+// Conversions to f32 for output files likewise do nothing in single precision builds.
+#![cfg_attr(feature = "f32", allow(clippy::excessive_precision, clippy::unnecessary_cast))]
+
+pub mod aov;
+pub mod bvh;
+pub mod camera;
+pub mod csg;
+pub mod denoise;
+pub mod environment;
+pub mod exr;
+pub mod layered;
+pub mod lights;
+pub mod materials;
+pub mod mesh;
+pub mod microfacet;
+pub mod normalmap;
+pub mod objects;
+pub mod principled;
+pub mod scene;
+pub mod simd;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
+pub mod util;
+pub mod vec;
+pub mod volume;
+
+use vec::{Ray, Vec3};
@@ -0,0 +1,134 @@
+use crate::vec::{Color, Float, Point, Vec3};
+
+/// Light arriving at a point from a light
+pub struct LightSample {
+    /// Unit vector from the point towards the light
+    pub direction: Vec3,
+    /// How far away the light is, which is infinite for directional lights
+    pub distance: Float,
+    /// Light arriving at the point if nothing is in the way, already divided by the square of the distance
+    pub irradiance: Color,
+}
+
+/// A light with no size, which can only be found by sampling it
+pub trait Light {
+    /// Light reaching `point` from this light, or `None` if it cannot reach the point at all
+    fn sample(&self, point: &Point) -> Option<LightSample>;
+}
+
+/// Shines equally in every direction from a single point
+pub struct PointLight {
+    position: Point,
+    // Power per unit solid angle
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: &Point) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light.normalize(),
+            distance: distance_squared.sqrt(),
+            irradiance: (1.0 / distance_squared) * self.intensity
+        })
+    }
+}
+
+/// A point light restricted to a cone, fading out between an inner and outer angle
+pub struct SpotLight {
+    position: Point,
+    // Unit vector along the center of the cone
+    direction: Vec3,
+    intensity: Color,
+    cos_inner: Float,
+    cos_outer: Float,
+}
+
+impl SpotLight {
+    /// Points the light from `position` at `target`, with the cone's angles measured from its center in degrees
+    pub fn new(position: Point, target: Point, intensity: Color, inner_angle: Float, outer_angle: Float) -> Self {
+        assert!(0.0 <= inner_angle && inner_angle <= outer_angle && outer_angle <= 180.0, "Spot light angles must satisfy 0 <= inner <= outer <= 180");
+        let direction = (target - position).normalize();
+        Self {
+            position,
+            direction,
+            intensity,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos()
+        }
+    }
+
+    /// How much of the light's intensity leaves in a direction, smoothly fading from 1 inside the inner angle to 0 outside the outer
+    fn falloff(&self, direction: &Vec3) -> Float {
+        let cos = self.direction.dot(direction);
+        if cos >= self.cos_inner {
+            1.0
+        } else if cos <= self.cos_outer {
+            0.0
+        } else {
+            let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Point) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let direction = to_light.normalize();
+        let falloff = self.falloff(&-&direction);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: distance_squared.sqrt(),
+            irradiance: (falloff / distance_squared) * self.intensity
+        })
+    }
+}
+
+/// Light from infinitely far away, arriving from the same direction everywhere, like sunlight
+pub struct DirectionalLight {
+    // Unit vector pointing back towards where the light comes from
+    to_light: Vec3,
+    // Power per unit area facing the light
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    /// `direction` is the way the light travels
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            to_light: -direction.normalize(),
+            irradiance
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: &Point) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.to_light,
+            distance: Float::INFINITY,
+            irradiance: self.irradiance
+        })
+    }
+}
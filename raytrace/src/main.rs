@@ -1,63 +1,192 @@
 extern crate image;
 
 use std::{sync::Arc, time::Instant};
-use image::{math::utils::clamp, RgbImage};
+use image::RgbImage;
 use rayon::prelude::*;
 
-mod camera;
-mod materials;
-mod objects;
-mod util;
-mod vec;
-
-use camera::*;
-use materials::*;
-use objects::*;
-use util::*;
-use vec::*;
+use raytrace::{aov::{self, Region}, camera::*, denoise::Denoiser, environment::*, materials::*, objects::*, scene::Scene, simd::LANES, spectrum, vec::*};
 use rand::Rng;
 
 const BG_COLOR_TOP: Color = Vec3(0.3, 0.5, 1.0);
 const BG_COLOR_BOTTOM: Color = Vec3(1.0, 1.0, 1.0);
 const MAX_DEPTH: i32 = 10;
+// Steps of random walks inside objects, such as subsurface scattering, count against this instead of `MAX_DEPTH`,
+// since light often takes hundreds of them to find its way out
+const MAX_WALK_STEPS: i32 = 256;
+// Traces a single wavelength per sample, so that dispersive materials split light into colors
+const SPECTRAL: bool = false;
+// Also writes a copy of the image with its noise smoothed out, next to the original
+const DENOISE: bool = true;
 
-fn ray_color(ray: &Ray, hittables: &Hittables, depth: i32) -> Color {
-    if depth <= 0 {
-        Vec3(0.0, 0.0, 0.0)
+// A min of some small value helps to abvoid floating point errors causing fake hits
+const T_MIN: Float = 0.0001;
+
+/// Light leaving a point towards the viewer, split by how it got there
+struct Radiance {
+    /// Given off by whatever the ray hit, or by the environment when it hit nothing
+    emitted: Color,
+    /// Scattered towards the viewer straight after arriving from a light, an emitter or the environment
+    direct: Color,
+    /// Scattered towards the viewer after bouncing more than once
+    indirect: Color,
+}
+
+impl Radiance {
+    fn black() -> Self {
+        Self {
+            emitted: Vec3(0.0, 0.0, 0.0),
+            direct: Vec3(0.0, 0.0, 0.0),
+            indirect: Vec3(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// `bsdf_pdf` is the density the previous bounce chose the ray's direction with,
+/// or `None` when sampling the environment could not have found the same direction.
+fn ray_color(ray: &Ray, scene: &Scene, depth: i32, walk_steps: i32, bsdf_pdf: Option<Float>) -> Radiance {
+    if depth <= 0 || walk_steps <= 0 {
+        Radiance::black()
     } else {
-        // A min of some small value helps to abvoid floating point errors causing fake hits
-        match hittables.hit(ray, 0.0001, f64::INFINITY) {
-            Some(hit) => {
-                if let Some((attenuation, ray)) = hit.material().scatter(ray, &hit) {
-                    attenuation * ray_color(&ray, hittables, depth - 1)
+        shade(ray, scene.hit(ray, T_MIN, Float::INFINITY), scene, depth, walk_steps, bsdf_pdf)
+    }
+}
+
+/// Like `ray_color`, for a ray whose closest hit has already been found
+fn shade(ray: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: i32, walk_steps: i32, bsdf_pdf: Option<Float>) -> Radiance {
+    match hit {
+        Some(hit) => {
+            let wo = -ray.direction().normalize();
+            // Emitters are not sampled as lights, so hitting one is the only way to find them and there is nothing to weigh against
+            let emitted = spectrum::project(hit.material().emitted(&hit, &wo), ray.wavelength());
+            let mut direct = if !hit.material().samples_lights() {
+                Vec3(0.0, 0.0, 0.0)
+            } else {
+                sample_environment(ray, &hit, scene) + sample_lights(ray, &hit, scene)
+            };
+            let mut indirect = Vec3(0.0, 0.0, 0.0);
+            if let Some(sample) = hit.material().sample(ray, &hit, (rand::random(), rand::random())) {
+                // Only mirror and glass bounces keep a footprint narrow enough to be worth tracking
+                let differential = if sample.is_delta && sample.ray.origin() == hit.point() {
+                    hit.specular_differential(ray, sample.ray.direction())
                 } else {
-                    Vec3(0.0, 0.0, 0.0)
-                }
-            },
-            None => {
-                let t = 0.5 * (ray.direction().normalize().1 + 1.0);
-                let ret = lerp(BG_COLOR_BOTTOM, BG_COLOR_TOP, t);
-                ret
+                    None
+                };
+                let scattered = sample.ray.with_wavelength(ray.wavelength()).with_differential(differential);
+                let pdf = if sample.is_delta { None } else { Some(sample.pdf) };
+                let weight = spectrum::project(sample.weight, ray.wavelength());
+                // Hits from inside a walking material are steps of its walk, and entering or leaving it is a bounce
+                let next = if !hit.is_outside && hit.material().walks_inside() {
+                    ray_color(&scattered, scene, depth, walk_steps - 1, pdf)
+                } else {
+                    ray_color(&scattered, scene, depth - 1, walk_steps, pdf)
+                };
+                // Whatever the bounce found glowing reached here in one step, and everything else took more
+                direct = direct + weight * next.emitted;
+                indirect = weight * (next.direct + next.indirect);
             }
+            Radiance { emitted, direct, indirect }
+        },
+        None => {
+            let environment = scene.environment();
+            let radiance = spectrum::project(environment.radiance(ray.direction()), ray.wavelength());
+            let emitted = match bsdf_pdf {
+                // Weighed against sampling the environment, by the balance heuristic
+                Some(pdf) => (pdf / (pdf + environment.pdf(ray.direction()))) * radiance,
+                None => radiance
+            };
+            Radiance { emitted, ..Radiance::black() }
         }
     }
 }
 
+/// Light arriving at a hit straight from the environment, found by sampling the environment and checking that nothing is in the way
+fn sample_environment(ray: &Ray, hit: &HitRecord, scene: &Scene) -> Color {
+    let environment = scene.environment();
+    let (wi, light_pdf) = environment.sample((rand::random(), rand::random()));
+    if light_pdf <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let wo = -ray.direction().normalize();
+    let f = hit.material().eval(hit, &wo, &wi);
+    if f == Vec3(0.0, 0.0, 0.0) {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+    let visibility = scene.transmittance(&Ray::new(hit.point(), &wi).with_wavelength(ray.wavelength()), T_MIN, Float::INFINITY);
+    if visibility <= 0.0 {
+        return Vec3(0.0, 0.0, 0.0);
+    }
+
+    let bsdf_pdf = hit.material().pdf(hit, &wo, &wi);
+    let weight = visibility / (light_pdf + bsdf_pdf);
+    weight * (spectrum::project(f, ray.wavelength()) * spectrum::project(environment.radiance(&wi), ray.wavelength()))
+}
+
+/// Light arriving at a hit straight from each of the scene's lights, wherever nothing is in the way
+fn sample_lights(ray: &Ray, hit: &HitRecord, scene: &Scene) -> Color {
+    let wo = -ray.direction().normalize();
+    scene.lights().iter().filter_map(|light| {
+        let sample = light.sample(hit.point())?;
+        let f = hit.material().eval(hit, &wo, &sample.direction);
+        let shadow = Ray::new(hit.point(), &sample.direction).with_wavelength(ray.wavelength());
+        // Lights are points, so nothing else could have found them and there is nothing to weigh against
+        if f == Vec3(0.0, 0.0, 0.0) {
+            return None;
+        }
+        let visibility = scene.transmittance(&shadow, T_MIN, sample.distance);
+        if visibility <= 0.0 {
+            return None;
+        }
+        Some(visibility * spectrum::project(f, ray.wavelength()) * spectrum::project(sample.irradiance, ray.wavelength()))
+    }).sum()
+}
+
+/// Converts the average of a pixel's samples to 8 bit, gamma corrected
 fn to_color(color: &Color) -> image::Rgb<u8> {
-    let r = (256.0 * clamp((color.0 / SAMPLES_PER_PIXEL as f64).sqrt(), 0.0, 0.999)).floor() as u8;
-    let g = (256.0 * clamp((color.1 / SAMPLES_PER_PIXEL as f64).sqrt(), 0.0, 0.999)).floor() as u8;
-    let b = (256.0 * clamp((color.2 / SAMPLES_PER_PIXEL as f64).sqrt(), 0.0, 0.999)).floor() as u8;
+    let r = (256.0 * color.0.sqrt().clamp(0.0, 0.999)).floor() as u8;
+    let g = (256.0 * color.1.sqrt().clamp(0.0, 0.999)).floor() as u8;
+    let b = (256.0 * color.2.sqrt().clamp(0.0, 0.999)).floor() as u8;
     image::Rgb([r, g, b])
 }
 
+/// Saves the pixels of `region` on their own
+fn save_png(path: &str, pixels: &[Color], region: &Region) {
+    let mut img = RgbImage::new(region.width as u32, region.height as u32);
+    for (i, color) in pixels.iter().enumerate() {
+        img.put_pixel((i % region.width) as u32, (i / region.width) as u32, to_color(color));
+    }
+    img.save(path).unwrap();
+}
+
+/// Opens a render of the whole frame to paste regions into
+fn open_base(path: &str) -> RgbImage {
+    let img = image::open(path).unwrap().to_rgb8();
+    assert!(img.dimensions() == (IMAGE_WIDTH, IMAGE_HEIGHT), "{} must be the size of the whole frame to composite onto", path);
+    img
+}
+
+/// Saves `base` with the pixels of `region` pasted into it where they belong in the frame
+fn save_composite(path: &str, mut base: RgbImage, pixels: &[Color], region: &Region) {
+    for (i, color) in pixels.iter().enumerate() {
+        base.put_pixel((region.x + i % region.width) as u32, (region.y + i / region.width) as u32, to_color(color));
+    }
+    base.save(path).unwrap();
+}
+
 const SAMPLES_PER_PIXEL: i32 = 100;
 
 const IMAGE_WIDTH: u32 = 1024;
-const ASPECT_RATIO: f64 = 16.0 / 9.0;
-const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
+const ASPECT_RATIO: Float = 16.0 / 9.0;
+const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as Float / ASPECT_RATIO) as u32;
 
-const FOV_DEG: f64 = 20.0;
-const APETURE: f64 = 0.1;
+// Renders only this rectangle of the frame, framed exactly as in the whole image, such as to look closely at one object
+const REGION: Option<Region> = None;
+// Renders of the whole frame, such as earlier ones, to also save the region pasted into as `image_composite.png`.
+// The denoised region goes onto its own base as `image_denoised_composite.png`, so that it never lands on a noisy one.
+const COMPOSITE_ONTO: Option<&str> = None;
+const COMPOSITE_DENOISED_ONTO: Option<&str> = None;
+
+const FOV_DEG: Float = 20.0;
+const APETURE: Float = 0.1;
 const ORIGIN: Vec3 = Vec3(13.0, 2.0, 3.0);
 const TARGET: Vec3 = Vec3(0.0, 0.0, 0.0);
 
@@ -72,10 +201,10 @@ fn main() {
 
     for a in -11..11 {
         for b in -11..11 {
-            let center = Vec3(a as f64 + 1.9 * rand::random::<f64>(), 0.2, b as f64 + 1.9 * rand::random::<f64>());
+            let center = Vec3(a as Float + 1.9 * rand::random::<Float>(), 0.2, b as Float + 1.9 * rand::random::<Float>());
             
-            if (&center - Vec3(4.0, 0.2, 0.0)).length() > 0.9 {
-                let material: f64 = rand::random();
+            if (center - Vec3(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material: Float = rand::random();
 
                 if material < 0.8 {
                     hittables.push(Box::new(Sphere::new(center, 0.2, Arc::new(Lambertian::new(Color::random())))));
@@ -89,25 +218,76 @@ fn main() {
         }
     }
 
-    let camera = Camera::new(ORIGIN, TARGET, Vec3(0.0, 1.0, 0.0), FOV_DEG, APETURE, 10.0);
+    let scene = Scene::new(hittables, Box::new(Gradient::new(BG_COLOR_BOTTOM, BG_COLOR_TOP)));
+    let camera = Camera::new(ORIGIN, TARGET, Vec3(0.0, 1.0, 0.0), FOV_DEG, ASPECT_RATIO, APETURE, 10.0);
+
+    // Samples spread over a pixel each only need to filter textures over their share of it
+    let pixel_footprint = 1.0 / (SAMPLES_PER_PIXEL as Float).sqrt();
+
+    let region = REGION.unwrap_or(Region { x: 0, y: 0, width: IMAGE_WIDTH as usize, height: IMAGE_HEIGHT as usize });
+    let mut passes = aov::Passes::cropped(IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize, region);
+    // Opened before anything is saved, in case a base is an earlier render at one of the paths about to be written
+    let composite_base = COMPOSITE_ONTO.map(open_base);
+    let denoised_base = COMPOSITE_DENOISED_ONTO.filter(|_| DENOISE).map(open_base);
 
     let start = Instant::now();
     println!("Starting raytracing...");
-    let image_data: Vec<(u32, u32, Color)> = (0..IMAGE_HEIGHT).rev().collect::<Vec<u32>>().into_par_iter().map(|row| {
-        (0..IMAGE_WIDTH).collect::<Vec<u32>>().into_par_iter().map(|col| {
-            let color: Color = (0..SAMPLES_PER_PIXEL).collect::<Vec<i32>>().into_par_iter().map(|_| {
-                let u = (col as f64 + rand::random::<f64>()) / (IMAGE_WIDTH) as f64;
-                let v = (row as f64 + rand::random::<f64>()) / (IMAGE_HEIGHT) as f64;
-                ray_color(&camera.get_ray(u, v), &hittables, MAX_DEPTH)
-            }).sum();
-            (col, IMAGE_HEIGHT - row - 1, color)
-        }).collect::<Vec<(u32, u32, Color)>>()
+    let image_data: Vec<(usize, usize, aov::Pixel)> = (0..region.height).collect::<Vec<usize>>().into_par_iter().map(|y| {
+        // Rows count up from the bottom of the whole frame, so the camera frames the region as part of it
+        let row = IMAGE_HEIGHT as usize - 1 - (region.y + y);
+        (0..region.width).collect::<Vec<usize>>().into_par_iter().map(|x| {
+            let col = region.x + x;
+            let mut pixel = aov::Pixel::new();
+            // Camera rays through the same pixel are traced together as packets for their first hit
+            for first in (0..SAMPLES_PER_PIXEL).step_by(LANES) {
+                let count = LANES.min((SAMPLES_PER_PIXEL - first) as usize);
+                let wavelengths: Vec<Option<(Float, Float)>> = (0..count).map(|_| {
+                    if SPECTRAL { Some(spectrum::sample_wavelength(rand::random())) } else { None }
+                }).collect();
+                let rays: Vec<Ray> = wavelengths.iter().map(|wavelength| {
+                    let u = (col as Float + rand::random::<Float>()) / (IMAGE_WIDTH) as Float;
+                    let v = (row as Float + rand::random::<Float>()) / (IMAGE_HEIGHT) as Float;
+                    camera.get_ray_differential(u, v, pixel_footprint / IMAGE_WIDTH as Float, pixel_footprint / IMAGE_HEIGHT as Float)
+                        .with_wavelength(wavelength.map(|(w, _)| w))
+                }).collect();
+                let hits = scene.hit_packet(&rays, T_MIN, Float::INFINITY);
+                for ((ray, hit), wavelength) in rays.iter().zip(hits).zip(wavelengths) {
+                    let surface = hit.as_ref().map(aov::Surface::new);
+                    let radiance = shade(ray, hit, &scene, MAX_DEPTH, MAX_WALK_STEPS, None);
+                    let to_rgb = |color: Color| match wavelength {
+                        Some((wavelength, pdf)) => spectrum::to_rgb(color.0, wavelength, pdf),
+                        None => color
+                    };
+                    pixel.add(&aov::Sample {
+                        emission: to_rgb(radiance.emitted),
+                        direct: to_rgb(radiance.direct),
+                        indirect: to_rgb(radiance.indirect),
+                        surface
+                    });
+                }
+            }
+            (x, y, pixel)
+        }).collect::<Vec<(usize, usize, aov::Pixel)>>()
     }).flatten().collect();
     println!("Raytracing completed in {} seconds", start.elapsed().as_secs_f32());
 
-    let mut img = RgbImage::new(IMAGE_WIDTH, IMAGE_HEIGHT);
-    for (x, y, color) in image_data.iter() {
-        img.put_pixel(*x, *y, to_color(color));
+    for (x, y, pixel) in image_data.iter() {
+        passes.set(*x, *y, pixel);
+    }
+    save_png("./image.png", passes.beauty(), &region);
+    if let Some(base) = composite_base {
+        save_composite("./image_composite.png", base, passes.beauty(), &region);
+    }
+    // Every pass, in linear light, for compositing
+    passes.write_exr("./image.exr").unwrap();
+
+    if DENOISE {
+        let start = Instant::now();
+        let denoised = Denoiser::new().denoise(&passes);
+        println!("Denoising completed in {} seconds", start.elapsed().as_secs_f32());
+        save_png("./image_denoised.png", &denoised, &region);
+        if let Some(base) = denoised_base {
+            save_composite("./image_denoised_composite.png", base, &denoised, &region);
+        }
     }
-    img.save("./image.png").unwrap();
 }
@@ -1,7 +1,71 @@
-use crate::{objects::HitRecord, vec::{Vec3, Ray, Color}, util::*};
+use crate::{microfacet::Ggx, objects::HitRecord, spectrum::LAMBDA_DEFAULT, texture::Texture, vec::{consts::PI, Vec3, Ray, Color, Float, Onb}, util::*};
+use rand::Rng;
+use std::sync::Arc;
 
+/// A direction chosen by `Material::sample`
+pub struct BsdfSample {
+    /// The scattered ray
+    pub ray: Ray,
+    /// `eval` divided by `pdf`, the factor to multiply light coming back along the ray by
+    pub weight: Color,
+    /// Density of the direction with respect to solid angle.
+    /// For delta lobes this is instead the chance of the lobe being picked.
+    pub pdf: Float,
+    /// Whether the direction came from a perfectly specular lobe, which `eval` and `pdf` never report
+    pub is_delta: bool,
+}
+
+/// Directions are all unit vectors pointing away from the surface.
+/// `wo` is towards the viewer and `wi` is where light arrives from.
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Color, Ray)>;
+    /// The BSDF times the cosine of `wi` with the normal, excluding any delta lobes
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color;
+
+    /// Chooses a direction to continue the path in, from two uniform random numbers.
+    /// `ray` is the incoming ray, so `wo` is its reversed direction.
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample>;
+
+    /// Density with respect to solid angle of `sample` choosing `wi`, excluding any delta lobes
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float;
+
+    /// The surface's color with lighting taken out, roughly how much light it reflects overall
+    fn albedo(&self, hit: &HitRecord) -> Color;
+
+    /// Light given off towards `wo` by the surface itself
+    fn emitted(&self, _hit: &HitRecord, _wo: &Vec3) -> Color {
+        Vec3(0.0, 0.0, 0.0)
+    }
+
+    /// The normal lighting is worked out with, which materials that perturb it report instead of the geometric one
+    fn shading_normal(&self, hit: &HitRecord) -> Vec3 {
+        *hit.normal()
+    }
+
+    /// Whether every lobe is a delta, so that `eval` is always zero and sampling lights is pointless
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Whether light arriving straight from the scene's lights and environment is worth sampling,
+    /// which it is not for delta lobes or for surfaces that reflect nothing at all
+    fn samples_lights(&self) -> bool {
+        !self.is_delta()
+    }
+
+    /// Whether rays inside the object random walk through it, taking far more steps than surface bounces do
+    fn walks_inside(&self) -> bool {
+        false
+    }
+
+    /// Chance that a ray hitting the surface here stops rather than passing straight through it
+    fn opacity(&self, _hit: &HitRecord) -> Float {
+        1.0
+    }
+
+    /// Whether `opacity` may be below 1 anywhere, which makes hits on the surface need checking against it
+    fn has_cutouts(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -15,69 +79,701 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter<'a>(&'a self, _ray: &Ray, hit: &HitRecord) -> Option<(Color, Ray)> {
-        let scatter_direction = hit.normal() + random_unit_vector();
-        Some(
-            (self.color.clone(), Ray::new(hit.point(), &scatter_direction))
-        )
+    fn eval(&self, hit: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Color {
+        let cos = wi.dot(hit.normal());
+        if cos > 0.0 {
+            (cos / PI) * self.color
+        } else {
+            Vec3(0.0, 0.0, 0.0)
+        }
+    }
+
+    fn sample(&self, _ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        let local = cosine_hemisphere(u);
+        let scatter_direction = Onb::from_normal(hit.normal()).to_world(&local);
+        Some(BsdfSample {
+            ray: Ray::new(hit.point(), &scatter_direction),
+            // The cosine and pi cancel with the pdf
+            weight: self.color,
+            pdf: local.2 / PI,
+            is_delta: false
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Float {
+        wi.dot(hit.normal()).max(0.0) / PI
     }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.color
+    }
+}
+
+/// How much light a conductor reflects at a given angle
+#[derive(Debug, Clone)]
+enum Fresnel {
+    /// Schlick's approximation from the color at normal incidence
+    Schlick(Color),
+    /// Exact, from a complex refractive index per channel
+    Conductor { eta: Color, k: Color },
 }
 
+impl Fresnel {
+    fn reflectance(&self, cos: Float) -> Color {
+        match self {
+            Fresnel::Schlick(f0) => f0 + (1.0 - cos.clamp(0.0, 1.0)).powi(5) * (Vec3(1.0, 1.0, 1.0) - f0),
+            Fresnel::Conductor { eta, k } => Vec3(
+                fresnel_conductor(cos, eta.0, k.0),
+                fresnel_conductor(cos, eta.1, k.1),
+                fresnel_conductor(cos, eta.2, k.2)
+            ),
+        }
+    }
+}
+
+/// A GGX microfacet conductor
 pub struct Metal {
-    color: Color,
-    fuzz: f64,
+    fresnel: Fresnel,
+    distribution: Ggx,
 }
 
 impl Metal {
-    pub fn new(color: Color, fuzz: f64) -> Self {
-        let fuzz = if fuzz < 1.0 {fuzz} else {1.0};
-        Self {color, fuzz}
+    /// A metal reflecting `color` head on, with `fuzz` being the roughness of the surface
+    pub fn new(color: Color, fuzz: Float) -> Self {
+        Self {
+            fresnel: Fresnel::Schlick(color),
+            distribution: Ggx::from_roughness(fuzz)
+        }
+    }
+
+    /// A metal with the complex refractive index `eta + ik` for each channel
+    pub fn conductor(eta: Color, k: Color, roughness: Float) -> Self {
+        Self {
+            fresnel: Fresnel::Conductor { eta, k },
+            distribution: Ggx::from_roughness(roughness)
+        }
+    }
+
+    pub fn gold(roughness: Float) -> Self {
+        Self::conductor(Vec3(0.143, 0.374, 1.442), Vec3(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn copper(roughness: Float) -> Self {
+        Self::conductor(Vec3(0.200, 0.924, 1.102), Vec3(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn aluminium(roughness: Float) -> Self {
+        Self::conductor(Vec3(1.657, 0.880, 0.521), Vec3(9.224, 6.270, 4.837), roughness)
+    }
+
+    pub fn silver(roughness: Float) -> Self {
+        Self::conductor(Vec3(0.155, 0.117, 0.138), Vec3(4.828, 3.122, 2.147), roughness)
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Color, Ray)> {
-        let reflected = reflect(&ray.direction().normalize(), hit.normal());
-        let scattered = Ray::new(hit.point(), &(&reflected + random_sphere_point(self.fuzz)));
-        let attenuation = self.color.clone();
-        if scattered.direction().dot(hit.normal()) > 0.0 {
-            Some(
-                (attenuation, scattered)
-            )
-        } else {
-            None
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(wo);
+        let wi = frame.to_local(wi);
+        if wo.2 <= 0.0 || wi.2 <= 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        let m = (wo + wi).normalize();
+        let f = self.fresnel.reflectance(wo.dot(&m));
+        (self.distribution.d(&m) * self.distribution.g2(&wo, &wi) / (4.0 * wo.2)) * f
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(&-ray.direction().normalize());
+        if wo.2 <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            let wi = Vec3(-wo.0, -wo.1, wo.2);
+            return Some(BsdfSample {
+                ray: Ray::new(hit.point(), &frame.to_world(&wi)),
+                weight: self.fresnel.reflectance(wo.2),
+                pdf: 1.0,
+                is_delta: true
+            });
+        }
+
+        let m = self.distribution.sample_visible(&wo, u.0, u.1);
+        let wi = reflect(&-&wo, &m);
+        if wi.2 <= 0.0 {
+            return None;
+        }
+        // The distribution term cancels out when sampling visible normals
+        let weight = self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo);
+        Some(BsdfSample {
+            ray: Ray::new(hit.point(), &frame.to_world(&wi)),
+            weight: weight * self.fresnel.reflectance(wo.dot(&m)),
+            // Jacobian of reflecting about the microfacet normal
+            pdf: self.distribution.pdf_visible(&wo, &m) / (4.0 * wo.dot(&m)),
+            is_delta: false
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(wo);
+        let wi = frame.to_local(wi);
+        if wo.2 <= 0.0 || wi.2 <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).normalize();
+        self.distribution.pdf_visible(&wo, &m) / (4.0 * wo.dot(&m))
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.fresnel.reflectance(1.0)
+    }
+}
+
+/// Refractive index, which may vary with wavelength
+#[derive(Debug, Clone)]
+pub enum Ior {
+    Constant(Float),
+    /// `a + b / λ²`, with λ in micrometers
+    Cauchy { a: Float, b: Float },
+    /// `n² = 1 + Σ bᵢλ² / (λ² - cᵢ)`, with λ in micrometers
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Ior {
+    /// Schott N-BK7 crown glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653]
+    };
+    /// Fused silica
+    pub const FUSED_SILICA: Ior = Ior::Sellmeier {
+        b: [0.6961663, 0.4079426, 0.8974794],
+        c: [0.004679148, 0.01351206, 97.93400]
+    };
+    pub const DIAMOND: Ior = Ior::Cauchy { a: 2.385, b: 0.0117 };
+
+    /// The index at a wavelength in nanometers
+    pub fn at(&self, wavelength: Float) -> Float {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<Float>()).sqrt(),
         }
     }
 }
 
 pub struct Dielectric {
-    refraction_idx: f64,
+    ior: Ior,
+    // Only used by rough surfaces
+    distribution: Ggx,
+    // Fraction of each channel absorbed per unit distance inside the medium
+    absorption: Color,
 }
 
 impl Dielectric {
-    pub fn new(refraction_idx: f64) -> Self {
-        Self {refraction_idx}
+    pub fn new(refraction_idx: Float) -> Self {
+        Self::rough(refraction_idx, 0.0)
     }
-}
 
-impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Color, Ray)> {
-        let etai_etat = if hit.is_outside {
-            1.0 / self.refraction_idx
+    /// Glass with a wavelength dependent index, which splits light into colors when rendering spectrally
+    pub fn dispersive(ior: Ior) -> Self {
+        Self::new(1.0).with_dispersion(ior)
+    }
+
+    /// Frosted glass, with GGX microfacets of the given roughness
+    pub fn rough(refraction_idx: Float, roughness: Float) -> Self {
+        Self {
+            ior: Ior::Constant(refraction_idx),
+            distribution: Ggx::from_roughness(roughness),
+            absorption: Vec3(0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Replaces the refractive index with one that varies by wavelength
+    pub fn with_dispersion(mut self, ior: Ior) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn refraction_idx(&self, hit: &HitRecord) -> Float {
+        self.ior.at(hit.wavelength().unwrap_or(LAMBDA_DEFAULT))
+    }
+
+    /// Absorbs light travelling inside the medium by the Beer-Lambert law, with a coefficient per channel
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        assert!(absorption.0 >= 0.0 && absorption.1 >= 0.0 && absorption.2 >= 0.0, "Absorption must not be negative");
+        self.absorption = absorption;
+        self
+    }
+
+    /// Tints the medium so that white light becomes `color` after travelling `distance` through it
+    pub fn with_tint(self, color: Color, distance: Float) -> Self {
+        assert!(distance > 0.0, "Tint distance must be positive");
+        let coefficient = |c: Float| -c.clamp(0.000001, 1.0).ln() / distance;
+        self.with_absorption(Vec3(coefficient(color.0), coefficient(color.1), coefficient(color.2)))
+    }
+
+    /// Fraction of light surviving the path to the hit, which was inside the medium if it hit from the inside
+    fn transmittance(&self, hit: &HitRecord) -> Color {
+        if hit.is_outside {
+            Vec3(1.0, 1.0, 1.0)
         } else {
-            self.refraction_idx
+            let d = hit.distance();
+            Vec3((-self.absorption.0 * d).exp(), (-self.absorption.1 * d).exp(), (-self.absorption.2 * d).exp())
+        }
+    }
+
+    /// Ratio of the refractive index past the surface to the index on the side the ray is on
+    fn eta(&self, hit: &HitRecord) -> Float {
+        if hit.is_outside {
+            self.refraction_idx(hit)
+        } else {
+            1.0 / self.refraction_idx(hit)
+        }
+    }
+
+    /// The microfacet normal for light going between `wo` and `wi`, on the same side as the macro normal
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3, eta: Float) -> Vec3 {
+        let m = if wi.2 > 0.0 {
+            wo + wi
+        } else {
+            -(wo + eta * wi)
         };
+        let m = m.normalize();
+        if m.2 < 0.0 { -m } else { m }
+    }
+
+    /// How often rough glass samples reflection rather than refraction, from the Fresnel term at the macro normal.
+    /// It is kept away from 0 and 1 since microfacets tilted away from the normal can still do either.
+    fn reflect_chance(&self, cos_o: Float, eta: Float) -> Float {
+        fresnel_dielectric(cos_o, eta).clamp(0.05, 0.95)
+    }
+
+    fn sample_smooth(&self, ray: &Ray, hit: &HitRecord, u: Float) -> Option<BsdfSample> {
+        let etai_etat = 1.0 / self.eta(hit);
         let uv = &ray.direction().normalize();
         // Derived from trig definition of dot product and trig pythagorean identity
         let cos_theta = (-uv).dot(hit.normal()).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
-        let reflect_prob = schlick(cos_theta, etai_etat);
-        if etai_etat * sin_theta > 1.0 || rand::random::<f64>() < reflect_prob {
-            let reflected = reflect(uv, hit.normal());
-            Some((Vec3(1.0, 1.0, 1.0), Ray::new(hit.point(), &reflected)))
+        let reflect_prob = if etai_etat * sin_theta > 1.0 {
+            1.0
         } else {
-            let refracted = refract(uv, hit.normal(), etai_etat);
-            Some((Vec3(1.0, 1.0, 1.0), Ray::new(hit.point(), &refracted)))
+            schlick(cos_theta, etai_etat)
+        };
+        let (direction, pdf) = if u < reflect_prob {
+            (reflect(uv, hit.normal()), reflect_prob)
+        } else {
+            (refract(uv, hit.normal(), etai_etat), 1.0 - reflect_prob)
+        };
+        Some(BsdfSample {
+            ray: Ray::new(hit.point(), &direction),
+            weight: self.transmittance(hit),
+            pdf,
+            is_delta: true
+        })
+    }
+}
+
+impl Material for Dielectric {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Vec3(0.0, 0.0, 0.0);
         }
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(wo);
+        let wi = frame.to_local(wi);
+        if wo.2 <= 0.0 || wi.2 == 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+
+        let eta = self.eta(hit);
+        let m = self.half_vector(&wo, &wi, eta);
+        let cos_om = wo.dot(&m);
+        let cos_im = wi.dot(&m);
+        let fresnel = fresnel_dielectric(cos_om, eta);
+        let dg = self.distribution.d(&m) * self.distribution.g2(&wo, &wi);
+        let f = if wi.2 > 0.0 {
+            fresnel * dg / (4.0 * wo.2)
+        } else {
+            // Microfacets facing away from either direction cannot refract between them
+            if cos_om <= 0.0 || cos_im >= 0.0 {
+                return Vec3(0.0, 0.0, 0.0);
+            }
+            let denom = (cos_om + eta * cos_im).powi(2);
+            (1.0 - fresnel) * dg * cos_im.abs() * cos_om * eta.powi(2) / (wo.2 * denom)
+        };
+        f * self.transmittance(hit)
     }
-}
\ No newline at end of file
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            return self.sample_smooth(ray, hit, u.0);
+        }
+
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(&-ray.direction().normalize());
+        if wo.2 <= 0.0 {
+            return None;
+        }
+
+        let eta = self.eta(hit);
+        let (reflects, u0) = choose(u.0, self.reflect_chance(wo.2, eta));
+        let m = self.distribution.sample_visible(&wo, u0, u.1);
+        let wi = if reflects {
+            let wi = reflect(&-&wo, &m);
+            if wi.2 <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            // Past the microfacet's critical angle everything reflects, leaving nothing to refract
+            if fresnel_dielectric(wo.dot(&m), eta) >= 1.0 {
+                return None;
+            }
+            let wi = refract(&-&wo, &m, 1.0 / eta);
+            if wi.2 >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let (wo, wi) = (frame.to_world(&wo), frame.to_world(&wi));
+        let pdf = self.pdf(hit, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: (1.0 / pdf) * self.eval(hit, &wo, &wi),
+            ray: Ray::new(hit.point(), &wi),
+            pdf,
+            is_delta: false
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = Onb::from_normal(hit.normal());
+        let wo = frame.to_local(wo);
+        let wi = frame.to_local(wi);
+        if wo.2 <= 0.0 || wi.2 == 0.0 {
+            return 0.0;
+        }
+
+        let eta = self.eta(hit);
+        let m = self.half_vector(&wo, &wi, eta);
+        let cos_om = wo.dot(&m);
+        let cos_im = wi.dot(&m);
+        let reflect_chance = self.reflect_chance(wo.2, eta);
+        let pdf_m = self.distribution.pdf_visible(&wo, &m);
+        if wi.2 > 0.0 {
+            reflect_chance * pdf_m / (4.0 * cos_om)
+        } else {
+            if cos_im >= 0.0 {
+                return 0.0;
+            }
+            let denom = (cos_om + eta * cos_im).powi(2);
+            (1.0 - reflect_chance) * pdf_m * eta.powi(2) * cos_im.abs() / denom
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+
+    // Clear glass lets through everything, and its color comes from what is behind it
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Vec3(1.0, 1.0, 1.0)
+    }
+}
+
+/// Scatters equally in every direction, for use inside of media
+pub struct Isotropic {
+    albedo: Color,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Self {albedo}
+    }
+}
+
+impl Material for Isotropic {
+    // Media have no surface to be foreshortened by, so there is no cosine term
+    fn eval(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Color {
+        (1.0 / (4.0 * PI)) * self.albedo
+    }
+
+    fn sample(&self, _ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            ray: Ray::new(hit.point(), &uniform_sphere(u)),
+            weight: self.albedo,
+            pdf: 1.0 / (4.0 * PI),
+            is_delta: false
+        })
+    }
+
+    fn pdf(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Float {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// Cuts holes in another material, for leaves, fences and decals modelled as simple surfaces.
+/// Where the mask is 0 rays pass through as if nothing was there, and values in between are partly see through.
+pub struct Cutout {
+    base: Arc<dyn Material + Send + Sync>,
+    mask: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Cutout {
+    pub fn new(base: Arc<dyn Material + Send + Sync>, mask: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self {
+            base,
+            mask
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.base.eval(hit, wo, wi)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        self.base.sample(ray, hit, u)
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.base.pdf(hit, wo, wi)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.base.is_delta()
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base.samples_lights()
+    }
+
+    fn walks_inside(&self) -> bool {
+        self.base.walks_inside()
+    }
+
+    fn opacity(&self, hit: &HitRecord) -> Float {
+        self.mask.scalar(hit).clamp(0.0, 1.0) * self.base.opacity(hit)
+    }
+
+    fn has_cutouts(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.base.albedo(hit)
+    }
+
+    fn emitted(&self, hit: &HitRecord, wo: &Vec3) -> Color {
+        self.base.emitted(hit, wo)
+    }
+
+    fn shading_normal(&self, hit: &HitRecord) -> Vec3 {
+        self.base.shading_normal(hit)
+    }
+}
+
+/// Light scattering around beneath the surface, as in skin, wax and marble.
+/// Rays entering the object random walk through it until they leave, so it must be used on closed objects.
+/// The walk has no closed form, so samples are all deltas and `eval` and `pdf` are always zero.
+pub struct Subsurface {
+    // Chance of light surviving each scattering event
+    albedo: Color,
+    // Average distance travelled between scattering events
+    mean_free_path: Color,
+    ior: Float,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: Color) -> Self {
+        assert!(mean_free_path.0 > 0.0 && mean_free_path.1 > 0.0 && mean_free_path.2 > 0.0, "Mean free paths must be positive");
+        Self {
+            albedo,
+            mean_free_path,
+            ior: 1.4
+        }
+    }
+
+    /// Refractive index of the surface, which decides how much light reflects off of it rather than entering
+    pub fn with_ior(mut self, ior: Float) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn transmittance(&self, distance: Float) -> Color {
+        let mfp = &self.mean_free_path;
+        Vec3((-distance / mfp.0).exp(), (-distance / mfp.1).exp(), (-distance / mfp.2).exp())
+    }
+
+    /// Continues the walk of a ray that reached the surface from inside, having travelled `hit.distance()` since it last scattered.
+    /// Scattering on the way takes a third number for its direction, which comes from `rng`.
+    fn walk(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float), rng: &mut impl Rng) -> Option<BsdfSample> {
+        // Each channel has its own free flight distribution, so one is picked and the others are weighed against it
+        let extinction = Vec3(1.0 / self.mean_free_path.0, 1.0 / self.mean_free_path.1, 1.0 / self.mean_free_path.2);
+        let (channel, u0) = choose_among(u.0, &[1.0 / 3.0; 3]);
+        let scatter_chance = 1.0 - self.transmittance(hit.distance())[channel];
+        let (scatters, u0) = choose(u0, scatter_chance);
+
+        if scatters {
+            // A free flight cut off at the surface, which it did not reach
+            let flight = -(1.0 - u0 * scatter_chance).ln() / extinction[channel];
+            let transmittance = self.transmittance(flight);
+            let density = extinction * transmittance;
+            let pdf = (density.0 + density.1 + density.2) / 3.0;
+            let point = ray.origin() + flight * ray.direction().normalize();
+            Some(BsdfSample {
+                ray: Ray::new(&point, &uniform_sphere((u.1, rng.gen()))),
+                weight: (1.0 / pdf) * (self.albedo * density),
+                pdf: 1.0,
+                is_delta: true
+            })
+        } else {
+            // The normal faces into the object, since the ray hit from inside
+            let direction = Onb::from_normal(&-hit.normal()).to_world(&cosine_hemisphere((u0, u.1)));
+            let wo = -ray.direction().normalize();
+            let pdf = self.pdf(hit, &wo, &direction);
+            if pdf <= 0.0 {
+                return None;
+            }
+            Some(BsdfSample {
+                weight: (1.0 / pdf) * self.eval(hit, &wo, &direction),
+                ray: Ray::new(hit.point(), &direction),
+                pdf,
+                is_delta: false
+            })
+        }
+    }
+
+    /// Chance of a walk reaching the surface at `hit` without scattering, averaged over the channels it picks between
+    fn survival(&self, hit: &HitRecord) -> Float {
+        let transmittance = self.transmittance(hit.distance());
+        (transmittance.0 + transmittance.1 + transmittance.2) / 3.0
+    }
+}
+
+impl Material for Subsurface {
+    // Walks that reach the surface from inside leave it diffusely, which is the only lobe lights can be sampled for
+    fn eval(&self, hit: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Color {
+        // The normal faces into the object for hits from inside
+        let cos = -wi.dot(hit.normal());
+        if hit.is_outside || cos <= 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+        (cos / PI) * self.transmittance(hit.distance())
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        if !hit.is_outside {
+            return self.walk(ray, hit, u, &mut rand::thread_rng());
+        }
+
+        let direction = ray.direction().normalize();
+        let reflect_prob = schlick((-&direction).dot(hit.normal()).clamp(0.0, 1.0), self.ior);
+        let (reflects, u0) = choose(u.0, reflect_prob);
+        if reflects {
+            return Some(BsdfSample {
+                ray: Ray::new(hit.point(), &reflect(&direction, hit.normal())),
+                weight: Vec3(1.0, 1.0, 1.0),
+                pdf: reflect_prob,
+                is_delta: true
+            });
+        }
+
+        // Entering light is diffused by the rough boundary before it starts to walk
+        let inward = Onb::from_normal(&-hit.normal()).to_world(&cosine_hemisphere((u0, u.1)));
+        Some(BsdfSample {
+            ray: Ray::new(hit.point(), &inward),
+            weight: Vec3(1.0, 1.0, 1.0),
+            pdf: 1.0 - reflect_prob,
+            is_delta: true
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Float {
+        let cos = -wi.dot(hit.normal());
+        if hit.is_outside || cos <= 0.0 {
+            return 0.0;
+        }
+        // Leaving is only sampled when the walk gets this far
+        self.survival(hit) * cos / PI
+    }
+
+    fn walks_inside(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// A surface that glows with a constant radiance from its outer side, and reflects nothing.
+/// Only found by paths that happen to hit it, so small bright ones are noisy compared to the scene's lights.
+pub struct DiffuseLight {
+    radiance: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(radiance: Color) -> Self {
+        assert!(radiance.0 >= 0.0 && radiance.1 >= 0.0 && radiance.2 >= 0.0, "Emitted radiance must not be negative");
+        Self {radiance}
+    }
+}
+
+impl Material for DiffuseLight {
+    fn eval(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Color {
+        Vec3(0.0, 0.0, 0.0)
+    }
+
+    fn sample(&self, _ray: &Ray, _hit: &HitRecord, _u: (Float, Float)) -> Option<BsdfSample> {
+        None
+    }
+
+    fn pdf(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Float {
+        0.0
+    }
+
+    // Nothing is reflected, so there is no point sampling lights for it
+    fn samples_lights(&self) -> bool {
+        false
+    }
+
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        Vec3(0.0, 0.0, 0.0)
+    }
+
+    fn emitted(&self, hit: &HitRecord, _wo: &Vec3) -> Color {
+        if hit.is_outside {
+            self.radiance
+        } else {
+            Vec3(0.0, 0.0, 0.0)
+        }
+    }
+}
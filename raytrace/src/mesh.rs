@@ -0,0 +1,369 @@
+use crate::{Ray, Vec3, bvh::Tree, materials::Material, objects::{first_unmasked, Aabb, Hittable, HitRecord, Interval}, vec::{Float, Point}};
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+/// A triangle mesh with vertex data shared between faces.
+/// Normal and UV buffers, when present, are indexed the same way as the positions.
+pub struct TriangleMesh {
+    positions: Vec<Point>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(Float, Float)>,
+    indices: Vec<[usize; 3]>,
+    // One entry per face, indexing into `materials`
+    face_materials: Vec<usize>,
+    materials: Vec<Arc<dyn Material + Send + Sync>>,
+    bounds: Aabb,
+    // Over the faces, so that rays only test the faces near them
+    tree: Tree,
+    // Face numbers in the order the tree's leaves refer to them
+    tree_faces: Vec<usize>,
+    // Whether every edge is shared by exactly two faces, so that the mesh has an inside
+    closed: bool,
+}
+
+/// Where a ray crosses one face of a mesh
+pub struct MeshHit {
+    pub face: usize,
+    pub t: Float,
+    /// Barycentric weights of the second and third vertices
+    pub b1: Float,
+    pub b2: Float,
+}
+
+impl TriangleMesh {
+    /// Creates a flat shaded mesh with a single material
+    pub fn new(positions: Vec<Point>, indices: Vec<[usize; 3]>, material: Arc<dyn Material + Send + Sync>) -> Self {
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()), "Mesh indices must refer to existing vertices");
+        let bounds = Aabb::around(positions.iter());
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in indices.iter() {
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        let mut entries: Vec<(Aabb, usize)> = indices.iter().enumerate()
+            .map(|(face, &[a, b, c])| (Aabb::around([&positions[a], &positions[b], &positions[c]]), face))
+            .collect();
+        let tree = Tree::new(&mut entries);
+        Self {
+            positions,
+            normals: vec![],
+            uvs: vec![],
+            face_materials: vec![0; indices.len()],
+            indices,
+            materials: vec![material],
+            bounds,
+            tree,
+            tree_faces: entries.into_iter().map(|(_, face)| face).collect(),
+            closed: !edges.is_empty() && edges.values().all(|&count| count == 2)
+        }
+    }
+
+    /// Whether the mesh is watertight, with every edge shared by exactly two faces
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Uses the given per vertex normals for smooth shading
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(normals.len(), self.positions.len(), "Meshes must have one normal per vertex");
+        self.normals = normals.iter().map(|n| n.normalize()).collect();
+        self
+    }
+
+    /// Generates smooth per vertex normals by averaging the normals of adjacent faces, weighted by area
+    pub fn with_smooth_normals(self) -> Self {
+        let mut normals = vec![Vec3(0.0, 0.0, 0.0); self.positions.len()];
+        for face in self.indices.iter() {
+            let [p0, p1, p2] = self.face_positions(face);
+            // Not normalized, so that larger faces contribute more
+            let n = (p1 - p0).cross(&(p2 - p0));
+            for &i in face.iter() {
+                normals[i] = normals[i] + n;
+            }
+        }
+        self.with_normals(normals)
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<(Float, Float)>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len(), "Meshes must have one UV per vertex");
+        self.uvs = uvs;
+        self
+    }
+
+    /// Assigns a material to a group of faces, replacing the material they had before
+    pub fn set_group_material(&mut self, faces: Range<usize>, material: Arc<dyn Material + Send + Sync>) {
+        assert!(faces.end <= self.indices.len(), "Face group must be within the mesh");
+        self.materials.push(material);
+        let id = self.materials.len() - 1;
+        for face in self.face_materials[faces].iter_mut() {
+            *face = id;
+        }
+    }
+
+    fn face_positions(&self, face: &[usize; 3]) -> [&Point; 3] {
+        [&self.positions[face[0]], &self.positions[face[1]], &self.positions[face[2]]]
+    }
+
+    /// Möller–Trumbore intersection against a single face
+    fn hit_face(&self, face: usize, ray: &Ray, tmin: Float, tmax: Float) -> Option<MeshHit> {
+        let [p0, p1, p2] = self.face_positions(&self.indices[face]);
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = ray.direction().cross(&e2);
+        let det = e1.dot(&pvec);
+        if det.abs() <= 0.0000001 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let tvec = ray.origin() - p0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&e1);
+        let b2 = ray.direction().dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(&qvec) * inv_det;
+        if tmin < t && t < tmax {
+            Some(MeshHit {
+                face,
+                t,
+                b1,
+                b2
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The closest face crossed by the ray within the range
+    pub fn hit_mesh(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<MeshHit> {
+        let mut closest = None;
+        self.tree.traverse(ray, tmin, tmax, |leaf, tmax| {
+            for &face in self.tree_faces[leaf].iter() {
+                if let Some(hit) = self.hit_face(face, ray, tmin, *tmax) {
+                    *tmax = hit.t;
+                    closest = Some(hit);
+                }
+            }
+            false
+        });
+        closest
+    }
+
+    /// Whether any face crosses the ray within the range, stopping at the first one found
+    pub fn any_face(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        let mut found = false;
+        self.tree.traverse(ray, tmin, tmax, |leaf, _| {
+            found = self.tree_faces[leaf].iter().any(|&face| self.hit_face(face, ray, tmin, tmax).is_some());
+            found
+        });
+        found
+    }
+
+    /// Texture coordinates at a point on a face, or `None` if the mesh has no UVs
+    pub fn uv_at(&self, hit: &MeshHit) -> Option<(Float, Float)> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[hit.face];
+        let b0 = 1.0 - hit.b1 - hit.b2;
+        Some((
+            b0 * self.uvs[i0].0 + hit.b1 * self.uvs[i1].0 + hit.b2 * self.uvs[i2].0,
+            b0 * self.uvs[i0].1 + hit.b1 * self.uvs[i1].1 + hit.b2 * self.uvs[i2].1
+        ))
+    }
+
+    /// Every face crossed anywhere along the ray's line, ordered along it
+    fn all_hits(&self, ray: &Ray) -> Vec<MeshHit> {
+        let mut hits = vec![];
+        self.tree.traverse(ray, Float::NEG_INFINITY, Float::INFINITY, |leaf, _| {
+            hits.extend(self.tree_faces[leaf].iter().filter_map(|&face| self.hit_face(face, ray, Float::NEG_INFINITY, Float::INFINITY)));
+            false
+        });
+        hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal));
+        hits
+    }
+
+    fn record(&self, ray: &Ray, hit: &MeshHit) -> HitRecord<'_> {
+        let face = &self.indices[hit.face];
+        let [p0, p1, p2] = self.face_positions(face);
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+
+        // Without UVs the barycentrics stand in, so the tangent follows the first edge
+        let (uv, dpdu, dpdv) = match self.uv_at(hit) {
+            Some(uv) => {
+                let [uv0, uv1, uv2] = face.map(|i| self.uvs[i]);
+                let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+                let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+                let det = du1 * dv2 - dv1 * du2;
+                if det.abs() > 1e-12 {
+                    (uv, (dv2 * (p1 - p0) - dv1 * (p2 - p0)) / det, (du1 * (p2 - p0) - du2 * (p1 - p0)) / det)
+                } else {
+                    (uv, p1 - p0, p2 - p0)
+                }
+            },
+            None => ((hit.b1, hit.b2), p1 - p0, p2 - p0)
+        };
+
+        let material = self.materials[self.face_materials[hit.face]].as_ref();
+        let mut record = HitRecord::new(ray, hit.t, geometric_normal, material).with_uv(uv, &dpdu, &dpdv);
+        if !self.normals.is_empty() {
+            let b0 = 1.0 - hit.b1 - hit.b2;
+            let shading_normal = b0 * self.normals[face[0]] + hit.b1 * self.normals[face[1]] + hit.b2 * self.normals[face[2]];
+            record.set_shading_normal(shading_normal.normalize());
+        }
+        record
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let hit = self.hit_mesh(ray, tmin, tmax)?;
+        Some(self.record(ray, &hit))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.materials.iter().any(|m| m.has_cutouts()) {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.any_face(ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        if !self.closed {
+            return vec![];
+        }
+        let mut hits = self.all_hits(ray);
+        // A ray through an edge or vertex crosses several faces at once, which only counts as one crossing
+        let scale = ray.direction().length().max(1e-12);
+        hits.dedup_by(|b, a| (b.t - a.t).abs() * scale < 1e-9);
+        // Crossings alternate between entering and leaving, unless the ray only grazed the mesh somewhere
+        if !hits.len().is_multiple_of(2) {
+            return vec![];
+        }
+        hits.chunks(2).map(|pair| Interval {
+            enter: self.record(ray, &pair[0]),
+            exit: self.record(ray, &pair[1])
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn grey() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)))
+    }
+
+    /// The unit square on the XY plane, split into two faces along its diagonal
+    fn square() -> TriangleMesh {
+        let positions = vec![Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), Vec3(1.0, 1.0, 0.0)];
+        TriangleMesh::new(positions, vec![[0, 1, 2], [3, 2, 1]], grey())
+    }
+
+    fn down_from(x: Float, y: Float) -> Ray {
+        Ray::new(&Vec3(x, y, 1.0), &Vec3(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn hits_a_face_with_its_barycentrics() {
+        let mesh = square();
+        let hit = mesh.hit_mesh(&down_from(0.25, 0.5), 0.0, Float::INFINITY).unwrap();
+        assert_eq!((hit.face, hit.t, hit.b1, hit.b2), (0, 1.0, 0.25, 0.5));
+
+        let hit = mesh.hit_mesh(&down_from(0.75, 0.5), 0.0, Float::INFINITY).unwrap();
+        assert_eq!((hit.face, hit.t, hit.b1, hit.b2), (1, 1.0, 0.25, 0.5));
+    }
+
+    #[test]
+    fn misses_outside_of_the_faces_and_the_range() {
+        let mesh = square();
+        assert!(mesh.hit_mesh(&down_from(1.5, 0.5), 0.0, Float::INFINITY).is_none());
+        assert!(mesh.hit_mesh(&down_from(0.5, -0.01), 0.0, Float::INFINITY).is_none());
+        assert!(mesh.hit_mesh(&down_from(0.25, 0.5), 0.0, 0.5).is_none());
+        assert!(mesh.hit_mesh(&down_from(0.25, 0.5), 1.5, Float::INFINITY).is_none());
+        // Along the plane of the faces, so never through them
+        let parallel = Ray::new(&Vec3(-1.0, 0.5, 0.0), &Vec3(1.0, 0.0, 0.0));
+        assert!(mesh.hit_mesh(&parallel, 0.0, Float::INFINITY).is_none());
+        assert!(!mesh.any_face(&parallel, 0.0, Float::INFINITY));
+    }
+
+    #[test]
+    fn interpolates_uvs_with_the_barycentrics() {
+        let mesh = square().with_uvs(vec![(0.0, 0.0), (2.0, 0.0), (0.0, 4.0), (2.0, 4.0)]);
+        let ray = down_from(0.25, 0.5);
+        let hit = mesh.hit_mesh(&ray, 0.0, Float::INFINITY).unwrap();
+        assert_eq!(mesh.uv_at(&hit), Some((0.5, 2.0)));
+        assert_eq!(mesh.hit(&ray, 0.0, Float::INFINITY).unwrap().uv(), (0.5, 2.0));
+        assert_eq!(square().uv_at(&hit), None);
+    }
+
+    #[test]
+    fn group_materials_apply_to_their_faces_only() {
+        let first = grey();
+        let second = grey();
+        let mut mesh = TriangleMesh::new(vec![Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), Vec3(1.0, 1.0, 0.0)], vec![[0, 1, 2], [3, 2, 1]], first.clone());
+        mesh.set_group_material(1..2, second.clone());
+
+        let material_at = |x, y| mesh.hit(&down_from(x, y), 0.0, Float::INFINITY).unwrap().material() as *const _ as *const ();
+        assert_eq!(material_at(0.25, 0.25), Arc::as_ptr(&first) as *const ());
+        assert_eq!(material_at(0.75, 0.75), Arc::as_ptr(&second) as *const ());
+    }
+
+    #[test]
+    fn tree_finds_the_same_faces_as_testing_every_face() {
+        // A bumpy grid of faces, so that the tree has plenty of levels
+        let n = 24;
+        let height = |i: usize, j: usize| ((i * 7 + j * 13) % 5) as Float * 0.1;
+        let mut positions = vec![];
+        for j in 0..=n {
+            for i in 0..=n {
+                positions.push(Vec3(i as Float / n as Float, j as Float / n as Float, height(i, j)));
+            }
+        }
+        let vertex = |i: usize, j: usize| j * (n + 1) + i;
+        let mut indices = vec![];
+        for j in 0..n {
+            for i in 0..n {
+                indices.push([vertex(i, j), vertex(i + 1, j), vertex(i, j + 1)]);
+                indices.push([vertex(i + 1, j + 1), vertex(i, j + 1), vertex(i + 1, j)]);
+            }
+        }
+        let mesh = TriangleMesh::new(positions, indices, grey());
+
+        for k in 0..2000 {
+            let a = k as Float * 0.618034;
+            let origin = Vec3(a.fract() * 1.4 - 0.2, (a * 1.7).fract() * 1.4 - 0.2, 1.0);
+            let direction = Vec3((a * 2.3).fract() - 0.5, (a * 3.1).fract() - 0.5, -1.0);
+            let ray = Ray::new(&origin, &direction);
+
+            let mut closest: Option<MeshHit> = None;
+            for face in 0..mesh.indices.len() {
+                if let Some(hit) = mesh.hit_face(face, &ray, 0.0, closest.as_ref().map_or(Float::INFINITY, |c| c.t)) {
+                    closest = Some(hit);
+                }
+            }
+            let found = mesh.hit_mesh(&ray, 0.0, Float::INFINITY);
+            assert_eq!(found.as_ref().map(|hit| hit.t), closest.as_ref().map(|hit| hit.t), "Ray {} should hit the closest face", k);
+            assert_eq!(mesh.any_face(&ray, 0.0, Float::INFINITY), closest.is_some());
+            assert_eq!(mesh.all_hits(&ray).len(), (0..mesh.indices.len()).filter(|&face| mesh.hit_face(face, &ray, Float::NEG_INFINITY, Float::INFINITY).is_some()).count());
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::vec::{consts::{PI, TAU}, Float, Vec3};
+
+/// Isotropic GGX (Trowbridge-Reitz) microfacet distribution.
+/// All directions are in the surface's local space, where the macro normal is +Z.
+#[derive(Debug, Clone)]
+pub struct Ggx {
+    alpha: Float,
+}
+
+impl Ggx {
+    /// Roughness is perceptually linear, with 0 being a mirror and 1 being fully rough
+    pub fn from_roughness(roughness: Float) -> Self {
+        Self {
+            alpha: roughness.clamp(0.0, 1.0).powi(2).max(0.0001)
+        }
+    }
+
+    /// Below this a surface is indistinguishable from a perfect mirror and is better sampled as one
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 0.001
+    }
+
+    /// Density of microfacets with normal `m`
+    pub fn d(&self, m: &Vec3) -> Float {
+        if m.2 <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha.powi(2);
+        a2 / (PI * (m.2.powi(2) * (a2 - 1.0) + 1.0).powi(2))
+    }
+
+    fn lambda(&self, w: &Vec3) -> Float {
+        let cos2 = w.2.powi(2);
+        if cos2 == 0.0 {
+            return Float::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha.powi(2) * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from `w`
+    pub fn g1(&self, w: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both directions, with height correlated masking and shadowing
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal in proportion to how much of it is visible from `wo`.
+    /// Heitz 2018, "Sampling the GGX Distribution of Visible Normals"
+    pub fn sample_visible(&self, wo: &Vec3, u1: Float, u2: Float) -> Vec3 {
+        // Stretch to the hemisphere configuration
+        let vh = Vec3(self.alpha * wo.0, self.alpha * wo.1, wo.2).normalize();
+        let len2 = vh.0.powi(2) + vh.1.powi(2);
+        let t1 = if len2 > 0.0 {
+            Vec3(-vh.1, vh.0, 0.0) / len2.sqrt()
+        } else {
+            Vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = TAU * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.2);
+        let p2 = (1.0 - s) * (1.0 - p1.powi(2)).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1.powi(2) - p2.powi(2)).max(0.0).sqrt() * vh;
+
+        // Unstretch back to the ellipsoid configuration
+        Vec3(self.alpha * nh.0, self.alpha * nh.1, nh.2.max(0.0)).normalize()
+    }
+
+    /// Density of `sample_visible` returning `m`, with respect to solid angle around `m`
+    pub fn pdf_visible(&self, wo: &Vec3, m: &Vec3) -> Float {
+        if wo.2 <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.2
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    materials::{BsdfSample, Material},
+    objects::HitRecord,
+    texture::Texture,
+    vec::{Color, Float, Ray, Vec3},
+};
+use std::sync::Arc;
+
+// Step in texture coordinates used to find the slope of a height map
+const BUMP_DELTA: Float = 0.001;
+
+/// Where the perturbed normal comes from
+enum Perturbation {
+    /// Tangent space normals encoded as colors, with Z along the surface normal
+    Normal(Arc<dyn Texture + Send + Sync>),
+    /// Heights read from the first channel, and how far they displace the surface
+    Bump(Arc<dyn Texture + Send + Sync>, Float),
+}
+
+/// Shades another material with a normal perturbed by a texture, without changing the geometry
+pub struct NormalMapped {
+    base: Arc<dyn Material + Send + Sync>,
+    perturbation: Perturbation,
+}
+
+impl NormalMapped {
+    /// Uses a tangent space normal map, where each channel maps [0, 1] to [-1, 1]
+    pub fn normal_map(base: Arc<dyn Material + Send + Sync>, normals: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self {
+            base,
+            perturbation: Perturbation::Normal(normals)
+        }
+    }
+
+    /// Uses a height map, with `scale` being the height of a value of 1 relative to a unit of texture space
+    pub fn bump_map(base: Arc<dyn Material + Send + Sync>, heights: Arc<dyn Texture + Send + Sync>, scale: Float) -> Self {
+        Self {
+            base,
+            perturbation: Perturbation::Bump(heights, scale)
+        }
+    }
+
+    /// A copy of the hit with its shading frame perturbed
+    fn shade<'a>(&self, hit: &HitRecord<'a>) -> HitRecord<'a> {
+        let (t, b, n) = (hit.tangent(), hit.bitangent(), hit.normal());
+        let local = match &self.perturbation {
+            Perturbation::Normal(normals) => {
+                let c = normals.value(hit);
+                Vec3(2.0 * c.0 - 1.0, 2.0 * c.1 - 1.0, 2.0 * c.2 - 1.0)
+            },
+            Perturbation::Bump(heights, scale) => {
+                let (u, v) = hit.uv();
+                // Differences over about a pixel's footprint, so that detail finer than a pixel does not alias
+                let ((dudx, dvdx), (dudy, dvdy)) = hit.uv_derivatives();
+                let step = |d: Float| if d > 0.0 { d } else { BUMP_DELTA };
+                let (du, dv) = (step(0.5 * (dudx.abs() + dudy.abs())), step(0.5 * (dvdx.abs() + dvdy.abs())));
+                let height_at = |uv| heights.scalar(&hit.at_uv(uv));
+                let height = heights.scalar(hit);
+                let dhdu = (height_at((u + du, v)) - height) / du;
+                let dhdv = (height_at((u, v + dv)) - height) / dv;
+                Vec3(-scale * dhdu, -scale * dhdv, 1.0)
+            }
+        };
+
+        let normal = (local.0 * t + local.1 * b + local.2 * n).normalize();
+        let mut shaded = hit.clone();
+        // Maps that would tilt the normal past the surface are left unperturbed
+        if normal.dot(n) > 0.0 {
+            shaded.set_shading_normal(if hit.is_outside {normal} else {-normal});
+        }
+        shaded
+    }
+}
+
+impl Material for NormalMapped {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.base.eval(&self.shade(hit), wo, wi)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        self.base.sample(ray, &self.shade(hit), u)
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.base.pdf(&self.shade(hit), wo, wi)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.base.is_delta()
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base.samples_lights()
+    }
+
+    fn walks_inside(&self) -> bool {
+        self.base.walks_inside()
+    }
+
+    fn opacity(&self, hit: &HitRecord) -> Float {
+        self.base.opacity(hit)
+    }
+
+    fn has_cutouts(&self) -> bool {
+        self.base.has_cutouts()
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.base.albedo(hit)
+    }
+
+    fn emitted(&self, hit: &HitRecord, wo: &Vec3) -> Color {
+        self.base.emitted(hit, wo)
+    }
+
+    fn shading_normal(&self, hit: &HitRecord) -> Vec3 {
+        self.base.shading_normal(&self.shade(hit))
+    }
+}
@@ -1,32 +1,263 @@
-use crate::{Ray, Vec3, materials::Material, vec::Point};
+use crate::{Ray, Vec3, materials::Material, vec::{consts::PI, Float, Onb, Point, RayDifferential}};
 use std::sync::Arc;
 
-pub struct HitRecord {
+/// Borrows the material from the object that was hit, so records live no longer than the scene
+#[derive(Clone)]
+pub struct HitRecord<'a> {
     point: Vec3,
-    pub t: f64,
+    pub t: Float,
+    distance: Float,
+    wavelength: Option<Float>,
     normal: Vec3,
+    // Shading frame around the normal, with the tangent following increasing U
+    tangent: Vec3,
+    bitangent: Vec3,
+    uv: (Float, Float),
+    // How the surface moves as U and V increase, which is zero for objects without texture coordinates
+    dpdu: Vec3,
+    dpdv: Vec3,
+    // The ray's neighbours and the plane they are crossed with, kept so that only hits that get shaded pay for the crossing
+    differential: Option<RayDifferential>,
+    geometric_normal: Vec3,
     pub is_outside: bool,
-    material: Arc<dyn Material + Send + Sync>
+    material: &'a (dyn Material + Send + Sync),
+    // Which of the scene's objects was hit, or 0 when not known
+    object_id: u32,
 }
 
-impl HitRecord {
-    pub fn point<'a>(&'a self) -> &'a Point {
+impl<'a> HitRecord<'a> {
+    /// Records a hit at distance `t` along the ray, orienting the normal to face against the ray
+    pub fn new(ray: &Ray, t: Float, outward_normal: Vec3, material: &'a (dyn Material + Send + Sync)) -> Self {
+        let is_outside = ray.direction().dot(&outward_normal) < 0.0;
+        let normal = if is_outside {outward_normal} else {-outward_normal};
+        let frame = Onb::from_normal(&normal);
+        Self {
+            point: ray.at(t),
+            t,
+            distance: t * ray.direction().length(),
+            wavelength: ray.wavelength(),
+            tangent: frame.to_world(&Vec3(1.0, 0.0, 0.0)),
+            bitangent: frame.to_world(&Vec3(0.0, 1.0, 0.0)),
+            normal,
+            uv: (0.0, 0.0),
+            dpdu: Vec3(0.0, 0.0, 0.0),
+            dpdv: Vec3(0.0, 0.0, 0.0),
+            differential: ray.differential().copied(),
+            geometric_normal: normal,
+            is_outside,
+            material,
+            object_id: 0
+        }
+    }
+
+    /// Tags the hit with the scene object it belongs to, for telling objects apart in compositing
+    pub fn with_object_id(mut self, id: u32) -> Self {
+        self.object_id = id;
+        self
+    }
+
+    /// Sets the texture coordinates of the hit, along with how the surface moves as U and V increase
+    pub fn with_uv(mut self, uv: (Float, Float), dpdu: &Vec3, dpdv: &Vec3) -> Self {
+        self.uv = uv;
+        self.dpdu = *dpdu;
+        self.dpdv = *dpdv;
+        self.orient_tangent(dpdu);
+        self
+    }
+
+    /// A copy of the hit looked up at other texture coordinates, such as for finite differences over a texture
+    pub fn at_uv(&self, uv: (Float, Float)) -> Self {
+        Self {
+            uv,
+            ..self.clone()
+        }
+    }
+
+    /// Makes the shading frame follow `dpdu` as closely as the normal allows
+    fn orient_tangent(&mut self, dpdu: &Vec3) {
+        let tangent = (dpdu - dpdu.dot(&self.normal) * self.normal).normalize();
+        if tangent.length_squared() == 0.0 {
+            // Degenerate, such as at the pole of a sphere, so any frame will do
+            let frame = Onb::from_normal(&self.normal);
+            self.tangent = frame.to_world(&Vec3(1.0, 0.0, 0.0));
+            self.bitangent = frame.to_world(&Vec3(0.0, 1.0, 0.0));
+        } else {
+            self.bitangent = self.normal.cross(&tangent);
+            self.tangent = tangent;
+        }
+    }
+
+    /// Replaces the normal used for shading, keeping it on the same side as the surface normal
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.normal = if self.is_outside {outward_normal} else {-outward_normal};
+        let tangent = self.tangent;
+        self.orient_tangent(&tangent);
+    }
+
+    /// Whether the material's opacity lets the ray pass through here, decided randomly for partial opacity
+    pub fn is_masked(&self) -> bool {
+        let opacity = self.material.opacity(self);
+        opacity < 1.0 && rand::random::<Float>() >= opacity
+    }
+
+    pub fn point(&self) -> &Point {
         &self.point
     }
 
-    pub fn normal<'a>(&'a self) -> &'a Vec3 {
+    /// How far the ray travelled to reach the hit, unlike `t` which is scaled by the ray's length
+    pub fn distance(&self) -> Float {
+        self.distance
+    }
+
+    /// Wavelength of the ray that hit, when rendering spectrally
+    pub fn wavelength(&self) -> Option<Float> {
+        self.wavelength
+    }
+
+    pub fn normal(&self) -> &Vec3 {
         &self.normal
     }
 
-    pub fn material<'a>(&'a self) -> &'a Arc<dyn Material + Send + Sync> {
-        &self.material
+    pub fn tangent(&self) -> &Vec3 {
+        &self.tangent
+    }
+
+    pub fn bitangent(&self) -> &Vec3 {
+        &self.bitangent
+    }
+
+    pub fn uv(&self) -> (Float, Float) {
+        self.uv
     }
+
+    /// How far the hit moves across one pixel in X and in Y, found where the ray's neighbours cross the plane the hit is on.
+    /// Both are zero when the ray did not carry differentials.
+    fn position_derivatives(&self) -> (Vec3, Vec3) {
+        let d = match &self.differential {
+            Some(d) => d,
+            None => return (Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0))
+        };
+        let normal = self.geometric_normal;
+        let offset = |origin: &Point, direction: &Vec3| {
+            let denom = normal.dot(direction);
+            if denom.abs() < 1e-12 {
+                return Vec3(0.0, 0.0, 0.0);
+            }
+            let t = normal.dot(&(self.point - origin)) / denom;
+            *origin + t * direction - self.point
+        };
+        (offset(&d.x_origin, &d.x_direction), offset(&d.y_origin, &d.y_direction))
+    }
+
+    /// How far the texture coordinates move across one pixel in X and in Y, for filtering textures.
+    /// Both are zero when the ray did not carry differentials.
+    pub fn uv_derivatives(&self) -> ((Float, Float), (Float, Float)) {
+        let (dpdu, dpdv) = (&self.dpdu, &self.dpdv);
+        // Least squares fit of the change in UV that best explains the change in position across the pixel
+        let (a00, a01, a11) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+        let det = a00 * a11 - a01 * a01;
+        if self.differential.is_none() || det.abs() <= 1e-12 {
+            return ((0.0, 0.0), (0.0, 0.0));
+        }
+        let solve = |dp: &Vec3| {
+            let (b0, b1) = (dpdu.dot(dp), dpdv.dot(dp));
+            ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+        let (dpdx, dpdy) = self.position_derivatives();
+        (solve(&dpdx), solve(&dpdy))
+    }
+
+    /// Carries the ray's differentials over to a specular bounce in `direction` from the hit.
+    /// The surface is treated as flat, so curved mirrors blur textures they reflect less than they should.
+    pub fn specular_differential(&self, ray: &Ray, direction: &Vec3) -> Option<RayDifferential> {
+        let d = ray.differential()?;
+        let (dpdx, dpdy) = self.position_derivatives();
+        let n = self.normal;
+        let incoming = ray.direction().normalize();
+        let outgoing = direction.normalize();
+        let (dx, dy) = (d.x_direction.normalize() - incoming, d.y_direction.normalize() - incoming);
+
+        let scatter = |delta: Vec3| if outgoing.dot(&n) > 0.0 {
+            // Reflected about the same normal
+            outgoing + delta - 2.0 * delta.dot(&n) * n
+        } else {
+            // Refracted, with the ratio of indices found from how much the direction bent
+            let sin_i = incoming.cross(&n).length();
+            let eta = if sin_i > 1e-6 { outgoing.cross(&n).length() / sin_i } else { 1.0 };
+            let cos_i = -incoming.dot(&n);
+            let cos_t = -outgoing.dot(&n);
+            let dmu = (eta - eta * eta * cos_i / cos_t.max(1e-6)) * -delta.dot(&n);
+            outgoing + eta * delta + dmu * n
+        };
+        Some(RayDifferential {
+            x_origin: self.point + dpdx,
+            x_direction: scatter(dx),
+            y_origin: self.point + dpdy,
+            y_direction: scatter(dy)
+        })
+    }
+
+    pub fn material(&self) -> &'a (dyn Material + Send + Sync) {
+        self.material
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+}
+
+/// A span of a ray that is inside of a closed object
+pub struct Interval<'a> {
+    pub enter: HitRecord<'a>,
+    pub exit: HitRecord<'a>,
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>>;
+
+    /// Every span of the ray's infinite line that is inside the object, ordered along the ray.
+    /// Only closed objects have an inside, so by default there are none.
+    fn intervals(&self, _ray: &Ray) -> Vec<Interval<'_>> {
+        vec![]
+    }
+
+    /// Whether the object encloses a volume that `intervals` reports, as CSG and media need
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Whether anything blocks the ray within the range, for shadow rays that only need a yes or no.
+    /// Implementations can exit early and skip building records, as long as they respect opacity masks.
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        first_unmasked(self, ray, tmin, tmax).is_some()
+    }
+
+    /// Fraction of light passing along the ray within the range, for shadow rays.
+    /// Surfaces either let it all through or none of it, while media can estimate how much gets through.
+    fn transmittance(&self, ray: &Ray, tmin: Float, tmax: Float) -> Float {
+        if self.occluded(ray, tmin, tmax) { 0.0 } else { 1.0 }
+    }
+
+    /// Bounds around everything the object could be hit at, or `None` for objects without an end such as planes
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// The closest hit on an object that its material's opacity does not let the ray pass through
+pub fn first_unmasked<'a, H: Hittable + ?Sized>(item: &'a H, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'a>> {
+    // Masked out hits are skipped by searching again from just beyond them
+    let mut start = tmin;
+    while let Some(hit) = item.hit(ray, start, tmax) {
+        if !hit.is_masked() {
+            return Some(hit);
+        }
+        start = hit.t;
+    }
+    None
 }
 
+#[derive(Default)]
 pub struct Hittables {
     items: Vec<Box<dyn Hittable + Send + Sync>>,
 }
@@ -42,12 +273,12 @@ impl Hittables {
         self.items.push(item);
     }
 
-    pub fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+    pub fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
         let mut record = None;
         let mut closest = tmax;
 
         for item in self.items.iter() {
-            if let Some(hit) = item.hit(ray, tmin, closest) {
+            if let Some(hit) = first_unmasked(item.as_ref(), ray, tmin, closest) {
                 closest = hit.t;
                 record = Some(hit);
             }
@@ -56,19 +287,28 @@ impl Hittables {
         record
     }
 
+    /// Whether any item blocks the ray within the range, stopping at the first one found
+    pub fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        self.items.iter().any(|item| item.occluded(ray, tmin, tmax))
+    }
+
     pub fn pop(&mut self) -> Option<Box<dyn Hittable + Send + Sync>> {
         self.items.pop()
     }
+
+    pub fn into_items(self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        self.items
+    }
 }
 
 pub struct Sphere {
     center: Vec3,
-    radius: f64,
+    radius: Float,
     material: Arc<dyn Material + Send + Sync>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material + Send + Sync>) -> Self {
+    pub fn new(center: Vec3, radius: Float, material: Arc<dyn Material + Send + Sync>) -> Self {
         Self {
             center,
             radius,
@@ -77,13 +317,14 @@ impl Sphere {
     }
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
+impl Sphere {
+    /// Distance to the first intersection within the range
+    fn intersect(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<Float> {
         if tmax < tmin { return None }
 
-        let oc = ray.origin() - &self.center;
+        let oc = ray.origin() - self.center;
         let a = ray.direction().length_squared();
-        let half_b = oc.dot(&ray.direction());
+        let half_b = oc.dot(ray.direction());
         let c = oc.length_squared() - self.radius.powi(2);
         let disc = half_b.powi(2) - a*c;
 
@@ -93,29 +334,11 @@ impl Hittable for Sphere {
             let root = disc.sqrt();
             let t = (-half_b - root) / a;
             if tmin < t && t < tmax {
-                let point= ray.at(t);
-                let outward_normal = (&point - &self.center) / self.radius;
-                let is_outside = ray.direction().dot(&outward_normal) < 0.0;
-                Some(HitRecord {
-                    point: point.clone(),
-                    t,
-                    normal: if is_outside {outward_normal} else {-outward_normal},
-                    is_outside,
-                    material: Arc::clone(&self.material)
-                })
+                Some(t)
             } else {
                 let t = (-half_b + root) / a;
                 if tmin < t && t < tmax {
-                    let point= ray.at(t);
-                    let outward_normal = (&point - &self.center) / self.radius;
-                    let is_outside = ray.direction().dot(&outward_normal) < 0.0;
-                    Some(HitRecord {
-                        point: point.clone(),
-                        t,
-                        normal: if is_outside {outward_normal} else {-outward_normal},
-                        is_outside,
-                        material: Arc::clone(&self.material)
-                    })
+                    Some(t)
                 } else {
                     None
                 }
@@ -123,6 +346,65 @@ impl Hittable for Sphere {
 
         }
     }
+
+    fn record(&self, ray: &Ray, t: Float) -> HitRecord<'_> {
+        let outward_normal = (ray.at(t) - self.center) / self.radius;
+        // Longitude and latitude, with U increasing eastwards around the Y axis
+        let u = ((-outward_normal.2).atan2(outward_normal.0) + PI) / (2.0 * PI);
+        let v = (-outward_normal.1).acos() / PI;
+        let n = outward_normal;
+        let dpdu = (2.0 * PI * self.radius) * Vec3(n.2, 0.0, -n.0);
+        // Around the equator, and nothing at the poles where latitude has no direction
+        let ring = (n.0 * n.0 + n.2 * n.2).sqrt();
+        let dpdv = if ring > 0.0 {
+            (PI * self.radius) * Vec3(-n.1 * n.0 / ring, ring, -n.1 * n.2 / ring)
+        } else {
+            Vec3(0.0, 0.0, 0.0)
+        };
+        HitRecord::new(ray, t, outward_normal, self.material.as_ref()).with_uv((u, v), &dpdu, &dpdv)
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let t = self.intersect(ray, tmin, tmax)?;
+        Some(self.record(ray, t))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.material.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.intersect(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        Some(Aabb::new(&(self.center - r), &(self.center + r)))
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let oc = ray.origin() - self.center;
+        let a = ray.direction().length_squared();
+        let half_b = oc.dot(ray.direction());
+        let c = oc.length_squared() - self.radius.powi(2);
+        let disc = half_b.powi(2) - a*c;
+
+        // A ray that only grazes the sphere never goes inside
+        if disc <= 0.0 {
+            return vec![];
+        }
+
+        let root = disc.sqrt();
+        vec![Interval {
+            enter: self.record(ray, (-half_b - root) / a),
+            exit: self.record(ray, (-half_b + root) / a)
+        }]
+    }
 }
 
 pub struct Triangle {
@@ -138,7 +420,7 @@ impl Triangle {
         assert_ne!(p1, p2, "Points on a triangle must be unique");
         assert_ne!(p2, p3, "Points on a triangle must be unique");
         assert_ne!(p3, p1, "Points on a triangle must be unique");
-        let normal = (&p2 - &p1).cross(&(&p3 - &p1)).normalize();
+        let normal = (p2 - p1).cross(&(p3 - p1)).normalize();
         assert_ne!(normal.length_squared(), 0.0, "Points on a triangle must not be colinear");
         Self {
             p1,
@@ -150,26 +432,22 @@ impl Triangle {
     }
 }
 
-impl Hittable for Triangle {
-    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
-        let denom = self.normal.dot(&ray.direction());
+impl Triangle {
+    /// Distance to the intersection within the range, and the barycentric weights of the second and third points there
+    fn intersect(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, (Float, Float))> {
+        let denom = self.normal.dot(ray.direction());
         if denom.abs() > 0.0000001 {
             let d = self.normal.dot(&self.p1);
-            let t = (d - self.normal.dot(&ray.origin())) / denom;
+            let t = (d - self.normal.dot(ray.origin())) / denom;
             if tmin < t && t < tmax {
                 let point = ray.at(t);
 
-                let a = ((&self.p2 - &self.p1).cross(&(&point - &self.p1))).dot(&self.normal);
-                let b = ((&self.p3 - &self.p2).cross(&(&point - &self.p2))).dot(&self.normal);
-                let c =((&self.p1 - &self.p3).cross(&(&point - &self.p3))).dot(&self.normal);
+                let a = ((self.p2 - self.p1).cross(&(point - self.p1))).dot(&self.normal);
+                let b = ((self.p3 - self.p2).cross(&(point - self.p2))).dot(&self.normal);
+                let c =((self.p1 - self.p3).cross(&(point - self.p3))).dot(&self.normal);
                 if a >= 0.0 && b >= 0.0 && c >= 0.0 {
-                    return Some(HitRecord {
-                        point,
-                        t,
-                        normal: (&self.normal).clone(),
-                        is_outside: denom < 0.0,
-                        material: Arc::clone(&self.material)
-                    })
+                    let area = a + b + c;
+                    return Some((t, (c / area, a / area)))
                 }
             }
         }
@@ -178,36 +456,623 @@ impl Hittable for Triangle {
     }
 }
 
-pub struct Square {
-    t1: Triangle,
-    t2: Triangle,
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let (t, uv) = self.intersect(ray, tmin, tmax)?;
+        let record = HitRecord::new(ray, t, self.normal, self.material.as_ref());
+        Some(record.with_uv(uv, &(self.p2 - self.p1), &(self.p3 - self.p1)))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.material.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.intersect(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::around([&self.p1, &self.p2, &self.p3]))
+    }
+}
+
+/// A parallelogram with one corner at `origin` and sides along `u` and `v`
+pub struct Quad {
+    origin: Point,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // Maps a point on the plane to coordinates along `u` and `v`
+    w: Vec3,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Quad {
+    pub fn new(origin: Point, u: Vec3, v: Vec3, material: Arc<dyn Material + Send + Sync>) -> Self {
+        let n = u.cross(&v);
+        assert_ne!(n.length_squared(), 0.0, "Sides of a quad must not be colinear");
+        let w = n / n.length_squared();
+        Self {
+            origin,
+            u,
+            v,
+            normal: n.normalize(),
+            w,
+            material
+        }
+    }
+
+    /// Creates a parallelogram from three consecutive corners, the fourth being implied.
+    /// The normal faces the same way as a `Triangle` through the same corners.
+    pub fn from_corners(p1: Point, p2: Point, p3: Point, material: Arc<dyn Material + Send + Sync>) -> Self {
+        // The fourth corner is p1 + p3 - p2, so the side from p1 to it runs parallel to p2 to p3
+        Self::new(p1, p2 - p1, p3 - p2, material)
+    }
+}
+
+impl Quad {
+    /// Distance to the intersection within the range, and where on the quad it is
+    fn intersect(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, (Float, Float))> {
+        let denom = self.normal.dot(ray.direction());
+        if denom.abs() <= 0.0000001 {
+            return None;
+        }
+
+        let t = (self.origin - ray.origin()).dot(&self.normal) / denom;
+        if !(tmin < t && t < tmax) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let planar = point - self.origin;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !((0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta)) {
+            return None;
+        }
+        Some((t, (alpha, beta)))
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let (t, uv) = self.intersect(ray, tmin, tmax)?;
+        let record = HitRecord::new(ray, t, self.normal, self.material.as_ref());
+        Some(record.with_uv(uv, &self.u, &self.v))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.material.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.intersect(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::around(&[self.origin, self.origin + self.u, self.origin + self.v, self.origin + self.u + self.v]))
+    }
 }
 
+/// A `Quad` with equal sides and right angles
+pub struct Square(Quad);
+
 impl Square {
+    /// Creates a square from its corners in order around the edge.
+    /// Small errors in the input are tolerated, with `p4` being implied by the other three corners.
     pub fn new(p1: Vec3, p2: Vec3, p3: Vec3, p4: Vec3, material: Arc<dyn Material + Send + Sync>) -> Self {
-        // Side lengths
-        assert!(((&p1 - &p2).length() - (&p2 - &p3).length()).abs() < 0.00000001, "Sides of a square must be equal");
-        assert!(((&p2 - &p3).length() - (&p3 - &p4).length()).abs() < 0.00000001, "Sides of a square must be equal");
-        assert!(((&p3 - &p4).length() - (&p4 - &p1).length()).abs() < 0.00000001, "Sides of a square must be equal");
-        assert!(((&p4 - &p1).length() - (&p1 - &p2).length()).abs() < 0.00000001, "Sides of a square must be equal");
-
-        // Angles
-        assert!((&p1 - &p2).dot(&(&p2 - &p3)).abs() < 0.00000001, "Squares must have right angles");
-        assert!((&p2 - &p3).dot(&(&p3 - &p4)).abs() < 0.00000001, "Squares must have right angles");
-        assert!((&p3 - &p4).dot(&(&p4 - &p1)).abs() < 0.00000001, "Squares must have right angles");
-        assert!((&p4 - &p1).dot(&(&p1 - &p2)).abs() < 0.00000001, "Squares must have right angles");
-
-        let t1 = Triangle::new(p1.clone(), p2.clone(), p3.clone(), Arc::clone(&material));
-        let t2 = Triangle::new(p1.clone(), p3.clone(), p4.clone(), Arc::clone(&material));
-        Self {
-            t1,
-            t2
+        let sides = [p2 - p1, p3 - p2, p4 - p3, p1 - p4];
+        let side = sides[0].length();
+        let tolerance = 0.000001 * side;
+
+        for (i, a) in sides.iter().enumerate() {
+            let b = &sides[(i + 1) % 4];
+            assert!((b.length() - side).abs() < tolerance, "Sides of a square must be equal");
+            assert!(a.dot(b).abs() < tolerance * side, "Squares must have right angles");
         }
+        assert!((p1 + p3 - p2 - p4).length() < tolerance, "Corners of a square must be coplanar");
+
+        Self(Quad::from_corners(p1, p2, p3, material))
     }
 }
 
 impl Hittable for Square {
-    fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> Option<HitRecord> {
-        self.t1.hit(ray, tmin, tmax).or_else(|| self.t2.hit(ray, tmin, tmax))
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        self.0.hit(ray, tmin, tmax)
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        self.0.occluded(ray, tmin, tmax)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.0.bounding_box()
+    }
+}
+
+/// A flat polygon, which may be concave.
+/// The edges must not cross each other.
+pub struct Polygon {
+    origin: Point,
+    normal: Vec3,
+    // Orthonormal basis of the plane, used to flatten hits to 2d
+    u: Vec3,
+    v: Vec3,
+    vertices: Vec<(Float, Float)>,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Polygon {
+    /// Creates a polygon from its vertices in order around the edge.
+    /// Vertices which are slightly off the best fit plane are projected onto it.
+    pub fn new(vertices: &[Point], material: Arc<dyn Material + Send + Sync>) -> Self {
+        assert!(vertices.len() >= 3, "Polygons must have at least 3 vertices");
+
+        // Newell's method is robust to concave and slightly non planar polygons
+        let mut normal = Vec3(0.0, 0.0, 0.0);
+        for (i, a) in vertices.iter().enumerate() {
+            let b = &vertices[(i + 1) % vertices.len()];
+            normal = normal + Vec3(
+                (a.1 - b.1) * (a.2 + b.2),
+                (a.2 - b.2) * (a.0 + b.0),
+                (a.0 - b.0) * (a.1 + b.1)
+            );
+        }
+        let normal = normal.normalize();
+        assert_ne!(normal.length_squared(), 0.0, "Polygons must have a nonzero area");
+
+        let origin = vertices.iter().fold(Vec3(0.0, 0.0, 0.0), |acc, p| acc + p) / vertices.len() as Float;
+        let offset = vertices[0] - origin;
+        let u = (offset - offset.dot(&normal) * normal).normalize();
+        let v = normal.cross(&u);
+        let flat = vertices.iter().map(|p| {
+            let offset = p - origin;
+            (offset.dot(&u), offset.dot(&v))
+        }).collect();
+
+        Self {
+            origin,
+            normal,
+            u,
+            v,
+            vertices: flat,
+            material
+        }
+    }
+
+    /// Even-odd crossing test in the plane of the polygon
+    fn contains(&self, x: Float, y: Float) -> bool {
+        let mut inside = false;
+        let mut prev = self.vertices[self.vertices.len() - 1];
+        for &cur in self.vertices.iter() {
+            if (cur.1 > y) != (prev.1 > y) {
+                let cross_x = cur.0 + (y - cur.1) * (prev.0 - cur.0) / (prev.1 - cur.1);
+                if x < cross_x {
+                    inside = !inside;
+                }
+            }
+            prev = cur;
+        }
+        inside
     }
-}
\ No newline at end of file
+
+    /// Distance to the intersection within the range, and where on the polygon it is
+    fn intersect(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, (Float, Float))> {
+        let denom = self.normal.dot(ray.direction());
+        if denom.abs() <= 0.0000001 {
+            return None;
+        }
+
+        let t = (self.origin - ray.origin()).dot(&self.normal) / denom;
+        if !(tmin < t && t < tmax) {
+            return None;
+        }
+
+        let offset = ray.at(t) - self.origin;
+        // Texture coordinates are in world units from the center of the polygon
+        let uv = (offset.dot(&self.u), offset.dot(&self.v));
+        if !self.contains(uv.0, uv.1) {
+            return None;
+        }
+        Some((t, uv))
+    }
+}
+
+impl Hittable for Polygon {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let (t, uv) = self.intersect(ray, tmin, tmax)?;
+        let record = HitRecord::new(ray, t, self.normal, self.material.as_ref());
+        Some(record.with_uv(uv, &self.u, &self.v))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.material.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.intersect(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let vertices: Vec<Point> = self.vertices.iter().map(|&(x, y)| self.origin + x * self.u + y * self.v).collect();
+        Some(Aabb::around(&vertices))
+    }
+}
+
+/// One of the six faces of a box
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    NegX,
+    PosX,
+    NegY,
+    PosY,
+    NegZ,
+    PosZ,
+}
+
+impl Face {
+    fn on_axis(axis: usize, positive: bool) -> Face {
+        match (axis, positive) {
+            (0, false) => Face::NegX,
+            (0, true) => Face::PosX,
+            (1, false) => Face::NegY,
+            (1, true) => Face::PosY,
+            (2, false) => Face::NegZ,
+            _ => Face::PosZ,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn outward_normal(self) -> Vec3 {
+        match self {
+            Face::NegX => Vec3(-1.0, 0.0, 0.0),
+            Face::PosX => Vec3(1.0, 0.0, 0.0),
+            Face::NegY => Vec3(0.0, -1.0, 0.0),
+            Face::PosY => Vec3(0.0, 1.0, 0.0),
+            Face::NegZ => Vec3(0.0, 0.0, -1.0),
+            Face::PosZ => Vec3(0.0, 0.0, 1.0),
+        }
+    }
+}
+
+/// Axis aligned bounds, without any surface of their own
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    /// Creates bounds spanning the two opposite corners `p1` and `p2`
+    pub fn new(p1: &Point, p2: &Point) -> Self {
+        Self {
+            min: Vec3(p1.0.min(p2.0), p1.1.min(p2.1), p1.2.min(p2.2)),
+            max: Vec3(p1.0.max(p2.0), p1.1.max(p2.1), p1.2.max(p2.2))
+        }
+    }
+
+    /// The smallest bounds containing all of the points
+    pub fn around<'a>(points: impl IntoIterator<Item = &'a Point>) -> Self {
+        let mut min = Vec3(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = Vec3(Float::NEG_INFINITY, Float::NEG_INFINITY, Float::NEG_INFINITY);
+        for p in points {
+            min = Vec3(min.0.min(p.0), min.1.min(p.1), min.2.min(p.2));
+            max = Vec3(max.0.max(p.0), max.1.max(p.1), max.2.max(p.2));
+        }
+        Self {
+            min,
+            max
+        }
+    }
+
+    /// The smallest bounds containing both
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::around([&self.min, &self.max, &other.min, &other.max])
+    }
+
+    pub fn centroid(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Slab test against the infinite line of the ray.
+    /// Returns the distance and face where the line enters and leaves the box, which may be behind the origin.
+    pub fn slab(&self, ray: &Ray) -> Option<((Float, Face), (Float, Face))> {
+        let mut enter = (Float::NEG_INFINITY, Face::NegX);
+        let mut exit = (Float::INFINITY, Face::PosX);
+
+        for axis in 0..3 {
+            let inv = 1.0 / ray.direction()[axis];
+            let mut t0 = (self.min[axis] - ray.origin()[axis]) * inv;
+            let mut t1 = (self.max[axis] - ray.origin()[axis]) * inv;
+            // A ray travelling in the positive direction enters through the negative face
+            let mut near_face = Face::on_axis(axis, false);
+            let mut far_face = Face::on_axis(axis, true);
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+                std::mem::swap(&mut near_face, &mut far_face);
+            }
+
+            if t0 > enter.0 {
+                enter = (t0, near_face);
+            }
+            if t1 < exit.0 {
+                exit = (t1, far_face);
+            }
+            if exit.0 < enter.0 {
+                return None;
+            }
+        }
+
+        Some((enter, exit))
+    }
+
+    /// Whether any part of the ray within the range is inside the bounds
+    pub fn overlaps(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        match self.slab(ray) {
+            Some(((enter, _), (exit, _))) => enter <= tmax && exit >= tmin,
+            None => false
+        }
+    }
+}
+
+/// An axis aligned box, with an optional material for each face.
+/// Stands in for boxes built from six `Square`s.
+pub struct Cuboid {
+    bounds: Aabb,
+    // Indexed by `Face::index`
+    materials: [Arc<dyn Material + Send + Sync>; 6],
+}
+
+impl Cuboid {
+    /// Creates a box spanning the two opposite corners `p1` and `p2`
+    pub fn new(p1: Point, p2: Point, material: Arc<dyn Material + Send + Sync>) -> Self {
+        let materials = [
+            Arc::clone(&material),
+            Arc::clone(&material),
+            Arc::clone(&material),
+            Arc::clone(&material),
+            Arc::clone(&material),
+            material,
+        ];
+        Self::with_face_materials(p1, p2, materials)
+    }
+
+    /// Like `new`, but with one material per face, ordered -X, +X, -Y, +Y, -Z, +Z
+    pub fn with_face_materials(p1: Point, p2: Point, materials: [Arc<dyn Material + Send + Sync>; 6]) -> Self {
+        let bounds = Aabb::new(&p1, &p2);
+        let (min, max) = (&bounds.min, &bounds.max);
+        assert!(min.0 < max.0 && min.1 < max.1 && min.2 < max.2, "Cuboids must have a nonzero size on every axis");
+        Self {
+            bounds,
+            materials
+        }
+    }
+
+    pub fn bounds(&self) -> &Aabb {
+        &self.bounds
+    }
+
+    fn has_cutouts(&self) -> bool {
+        self.materials.iter().any(|m| m.has_cutouts())
+    }
+
+    /// Records a hit on a face, given where it is in the box's own space and how to bring directions back out of it
+    fn record(&self, ray: &Ray, t: Float, local_point: &Point, face: Face, to_world: impl Fn(&Vec3) -> Vec3) -> HitRecord<'_> {
+        // Texture coordinates run across each face along the next two axes in order
+        let axis = face.index() / 2;
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let (min, max) = (&self.bounds.min, &self.bounds.max);
+        let coord = |a: usize| (local_point[a] - min[a]) / (max[a] - min[a]);
+        let dpdu = (max[u_axis] - min[u_axis]) * to_world(&Face::on_axis(u_axis, true).outward_normal());
+        let dpdv = (max[v_axis] - min[v_axis]) * to_world(&Face::on_axis(v_axis, true).outward_normal());
+
+        let outward_normal = to_world(&face.outward_normal());
+        HitRecord::new(ray, t, outward_normal, self.materials[face.index()].as_ref())
+            .with_uv((coord(u_axis), coord(v_axis)), &dpdu, &dpdv)
+    }
+
+    /// The distance to and face of the first intersection within the range
+    pub fn hit_face(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, Face)> {
+        let (enter, exit) = self.bounds.slab(ray)?;
+        if tmin < enter.0 && enter.0 < tmax {
+            Some(enter)
+        } else if tmin < exit.0 && exit.0 < tmax {
+            Some(exit)
+        } else {
+            None
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let (t, face) = self.hit_face(ray, tmin, tmax)?;
+        Some(self.record(ray, t, &ray.at(t), face, Vec3::clone))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.hit_face(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        match self.bounds.slab(ray) {
+            Some((enter, exit)) => {
+                let record = |(t, face): (Float, Face)| self.record(ray, t, &ray.at(t), face, Vec3::clone);
+                vec![Interval {
+                    enter: record(enter),
+                    exit: record(exit)
+                }]
+            },
+            None => vec![]
+        }
+    }
+}
+
+/// A box rotated to an arbitrary orientation.
+/// Faces are named by the box's local axes.
+pub struct OrientedCuboid {
+    center: Point,
+    axes: [Vec3; 3],
+    // Centered on the origin in the box's local space
+    local: Cuboid,
+}
+
+impl OrientedCuboid {
+    /// Creates a box of the given full `size` along its local axes.
+    /// The local X axis points along `forward`, and the local Y axis is as close to `up` as possible.
+    pub fn new(center: Point, size: Vec3, forward: Vec3, up: Vec3, material: Arc<dyn Material + Send + Sync>) -> Self {
+        let half = &size / 2.0;
+        Self::from_local(center, forward, up, Cuboid::new(-&half, half, material))
+    }
+
+    /// Like `new`, but with one material per local face, ordered -X, +X, -Y, +Y, -Z, +Z
+    pub fn with_face_materials(center: Point, size: Vec3, forward: Vec3, up: Vec3, materials: [Arc<dyn Material + Send + Sync>; 6]) -> Self {
+        let half = &size / 2.0;
+        Self::from_local(center, forward, up, Cuboid::with_face_materials(-&half, half, materials))
+    }
+
+    fn from_local(center: Point, forward: Vec3, up: Vec3, local: Cuboid) -> Self {
+        let x = forward.normalize();
+        let z = x.cross(&up).normalize();
+        assert_ne!(z.length_squared(), 0.0, "Forward and up directions of a box must not be colinear");
+        let y = z.cross(&x);
+        Self {
+            center,
+            axes: [x, y, z],
+            local
+        }
+    }
+
+    fn to_local(&self, ray: &Ray) -> Ray {
+        let offset = ray.origin() - self.center;
+        let dir = ray.direction();
+        Ray::new(
+            &Vec3(offset.dot(&self.axes[0]), offset.dot(&self.axes[1]), offset.dot(&self.axes[2])),
+            &Vec3(dir.dot(&self.axes[0]), dir.dot(&self.axes[1]), dir.dot(&self.axes[2]))
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.0 * self.axes[0] + v.1 * self.axes[1] + v.2 * self.axes[2]
+    }
+
+    /// The distance to and local face of the first intersection within the range
+    pub fn hit_face(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, Face)> {
+        // The transform is a rotation, so distances along the ray are unchanged
+        self.local.hit_face(&self.to_local(ray), tmin, tmax)
+    }
+}
+
+impl Hittable for OrientedCuboid {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let local = self.to_local(ray);
+        let (t, face) = self.local.hit_face(&local, tmin, tmax)?;
+        Some(self.local.record(ray, t, &local.at(t), face, |v| self.to_world(v)))
+    }
+
+    fn occluded(&self, ray: &Ray, tmin: Float, tmax: Float) -> bool {
+        if self.local.has_cutouts() {
+            return first_unmasked(self, ray, tmin, tmax).is_some();
+        }
+        self.hit_face(ray, tmin, tmax).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (min, max) = (self.local.bounds.min, self.local.bounds.max);
+        let corners: Vec<Point> = (0..8).map(|i| {
+            let corner = Vec3(if i & 1 == 0 { min.0 } else { max.0 }, if i & 2 == 0 { min.1 } else { max.1 }, if i & 4 == 0 { min.2 } else { max.2 });
+            self.center + self.to_world(&corner)
+        }).collect();
+        Some(Aabb::around(&corners))
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let local = self.to_local(ray);
+        match self.local.bounds.slab(&local) {
+            Some((enter, exit)) => {
+                let record = |(t, face): (Float, Face)| self.local.record(ray, t, &local.at(t), face, |v| self.to_world(v));
+                vec![Interval {
+                    enter: record(enter),
+                    exit: record(exit)
+                }]
+            },
+            None => vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn unit_cuboid() -> Cuboid {
+        Cuboid::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0), Arc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5))))
+    }
+
+    #[test]
+    fn cuboid_hits_each_face_from_outside() {
+        let cuboid = unit_cuboid();
+        for face in [Face::NegX, Face::PosX, Face::NegY, Face::PosY, Face::NegZ, Face::PosZ] {
+            let normal = face.outward_normal();
+            // Three units out from the face's side, heading back through the center
+            let ray = Ray::new(&(3.0 * normal), &-normal);
+            assert_eq!(cuboid.hit_face(&ray, 0.0, Float::INFINITY), Some((2.0, face)));
+
+            let hit = cuboid.hit(&ray, 0.0, Float::INFINITY).unwrap();
+            assert!(hit.is_outside);
+            assert_eq!(*hit.normal(), normal);
+            assert_eq!(*hit.point(), normal);
+        }
+    }
+
+    #[test]
+    fn cuboid_is_hit_on_the_way_out_from_inside() {
+        let cuboid = unit_cuboid();
+        let ray = Ray::new(&Vec3(0.0, 0.0, 0.0), &Vec3(0.0, 2.0, 0.0));
+        assert_eq!(cuboid.hit_face(&ray, 0.0, Float::INFINITY), Some((0.5, Face::PosY)));
+
+        let hit = cuboid.hit(&ray, 0.0, Float::INFINITY).unwrap();
+        assert!(!hit.is_outside);
+        // Normals face against the ray, so into the box here
+        assert_eq!(*hit.normal(), Vec3(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn cuboid_misses_outside_of_the_range() {
+        let cuboid = unit_cuboid();
+        let ray = Ray::new(&Vec3(-3.0, 0.0, 0.0), &Vec3(1.0, 0.0, 0.0));
+        assert_eq!(cuboid.hit_face(&ray, 0.0, 1.5), None);
+        assert_eq!(cuboid.hit_face(&ray, 4.5, Float::INFINITY), None);
+        assert_eq!(cuboid.hit_face(&ray, 2.5, Float::INFINITY), Some((4.0, Face::PosX)));
+    }
+
+    #[test]
+    fn slab_handles_rays_parallel_to_a_slab() {
+        let cuboid = unit_cuboid();
+        // Parallel to the Y and Z slabs, within them
+        let inside = Ray::new(&Vec3(-3.0, 0.5, -0.5), &Vec3(1.0, 0.0, 0.0));
+        assert_eq!(cuboid.bounds().slab(&inside), Some(((2.0, Face::NegX), (4.0, Face::PosX))));
+        assert_eq!(cuboid.hit_face(&inside, 0.0, Float::INFINITY), Some((2.0, Face::NegX)));
+
+        // Parallel to the Y slab, but above it
+        let outside = Ray::new(&Vec3(-3.0, 1.5, 0.0), &Vec3(1.0, 0.0, 0.0));
+        assert_eq!(cuboid.bounds().slab(&outside), None);
+        assert_eq!(cuboid.hit_face(&outside, 0.0, Float::INFINITY), None);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    materials::{BsdfSample, Dielectric, Material, Metal},
+    objects::HitRecord,
+    texture::Texture,
+    util::*,
+    vec::{consts::PI, Color, Float, Onb, Ray, Vec3},
+};
+use std::sync::Arc;
+
+// Keeps every lobe glossy rather than perfectly specular, so they can all be weighed against each other
+const MIN_ROUGHNESS: Float = 0.05;
+
+/// A Disney style principled material, described the way artists and DCC tools think about surfaces.
+/// Every parameter is a texture, with scalar parameters read from the first channel.
+pub struct Principled {
+    base_color: Arc<dyn Texture + Send + Sync>,
+    metallic: Arc<dyn Texture + Send + Sync>,
+    roughness: Arc<dyn Texture + Send + Sync>,
+    specular: Arc<dyn Texture + Send + Sync>,
+    clearcoat: Arc<dyn Texture + Send + Sync>,
+    clearcoat_roughness: Arc<dyn Texture + Send + Sync>,
+    sheen: Arc<dyn Texture + Send + Sync>,
+    transmission: Arc<dyn Texture + Send + Sync>,
+    ior: Float,
+}
+
+/// The lobes of a `Principled` material with its textures evaluated at one point
+struct Lobes {
+    base_color: Color,
+    diffuse: Float,
+    sheen: Float,
+    specular: Metal,
+    clearcoat: Metal,
+    clearcoat_weight: Float,
+    glass: Dielectric,
+    transmission: Float,
+    // Chance of sampling the diffuse, specular, clearcoat and transmission lobes
+    probabilities: [Float; 4],
+}
+
+impl Principled {
+    /// A rough dielectric of the given color, with every other parameter at its default
+    pub fn new(base_color: impl Texture + Send + Sync + 'static) -> Self {
+        Self {
+            base_color: Arc::new(base_color),
+            metallic: Arc::new(0.0),
+            roughness: Arc::new(0.5),
+            specular: Arc::new(0.5),
+            clearcoat: Arc::new(0.0),
+            clearcoat_roughness: Arc::new(0.1),
+            sheen: Arc::new(0.0),
+            transmission: Arc::new(0.0),
+            ior: 1.5
+        }
+    }
+
+    /// 0 is a dielectric, 1 is a metal tinted by the base color
+    pub fn with_metallic(mut self, metallic: impl Texture + Send + Sync + 'static) -> Self {
+        self.metallic = Arc::new(metallic);
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: impl Texture + Send + Sync + 'static) -> Self {
+        self.roughness = Arc::new(roughness);
+        self
+    }
+
+    /// Head on reflectance of dielectrics, where the default of 0.5 is 4%
+    pub fn with_specular(mut self, specular: impl Texture + Send + Sync + 'static) -> Self {
+        self.specular = Arc::new(specular);
+        self
+    }
+
+    /// Strength of a clear varnish layer on top of the rest of the material
+    pub fn with_clearcoat(mut self, clearcoat: impl Texture + Send + Sync + 'static, roughness: impl Texture + Send + Sync + 'static) -> Self {
+        self.clearcoat = Arc::new(clearcoat);
+        self.clearcoat_roughness = Arc::new(roughness);
+        self
+    }
+
+    /// Extra reflection at grazing angles, as seen on cloth
+    pub fn with_sheen(mut self, sheen: impl Texture + Send + Sync + 'static) -> Self {
+        self.sheen = Arc::new(sheen);
+        self
+    }
+
+    /// 0 is opaque, 1 is glass tinted by the base color
+    pub fn with_transmission(mut self, transmission: impl Texture + Send + Sync + 'static, ior: Float) -> Self {
+        self.transmission = Arc::new(transmission);
+        self.ior = ior;
+        self
+    }
+
+    fn lobes(&self, hit: &HitRecord) -> Lobes {
+        let base_color = self.base_color.value(hit);
+        let metallic = self.metallic.scalar(hit).clamp(0.0, 1.0);
+        let roughness = self.roughness.scalar(hit).clamp(MIN_ROUGHNESS, 1.0);
+        let transmission = (1.0 - metallic) * self.transmission.scalar(hit).clamp(0.0, 1.0);
+        let diffuse = (1.0 - metallic) * (1.0 - self.transmission.scalar(hit).clamp(0.0, 1.0));
+        let clearcoat_weight = 0.25 * self.clearcoat.scalar(hit).clamp(0.0, 1.0);
+
+        let dielectric_f0 = 0.08 * self.specular.scalar(hit).clamp(0.0, 1.0);
+        let f0 = lerp(Vec3(dielectric_f0, dielectric_f0, dielectric_f0), base_color, metallic);
+
+        let weights = [
+            diffuse * luminance(&base_color).max(0.05),
+            // Opaque dielectrics only reflect a few percent specularly, but it is concentrated
+            metallic + (1.0 - metallic) * (1.0 - transmission).max(0.0) * 0.25,
+            clearcoat_weight,
+            transmission,
+        ];
+        let total: Float = weights.iter().sum();
+        let probabilities = [weights[0] / total, weights[1] / total, weights[2] / total, weights[3] / total];
+
+        Lobes {
+            base_color,
+            diffuse,
+            sheen: (1.0 - metallic) * self.sheen.scalar(hit).max(0.0),
+            specular: Metal::new(f0, roughness),
+            clearcoat: Metal::new(Vec3(0.04, 0.04, 0.04), self.clearcoat_roughness.scalar(hit).clamp(MIN_ROUGHNESS, 1.0)),
+            clearcoat_weight,
+            glass: Dielectric::rough(self.ior, roughness),
+            transmission,
+            probabilities
+        }
+    }
+}
+
+impl Lobes {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        let n = hit.normal();
+        let cos_o = wo.dot(n);
+        let cos_i = wi.dot(n);
+        if cos_o <= 0.0 {
+            return Vec3(0.0, 0.0, 0.0);
+        }
+
+        let glass = self.transmission * self.glass.eval(hit, wo, wi);
+        if cos_i < 0.0 {
+            return glass * self.base_color;
+        }
+
+        let cos_d = wi.dot(&(wo + wi).normalize());
+        let sheen = self.sheen * (1.0 - cos_d).powi(5);
+        cos_i * ((self.diffuse / PI) * self.base_color + Vec3(sheen, sheen, sheen))
+            + self.specular.eval(hit, wo, wi)
+            + self.clearcoat_weight * self.clearcoat.eval(hit, wo, wi)
+            + glass
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let cos_i = wi.dot(hit.normal());
+        let diffuse = if cos_i > 0.0 { cos_i / PI } else { 0.0 };
+        self.probabilities[0] * diffuse
+            + self.probabilities[1] * self.specular.pdf(hit, wo, wi)
+            + self.probabilities[2] * self.clearcoat.pdf(hit, wo, wi)
+            + self.probabilities[3] * self.glass.pdf(hit, wo, wi)
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.lobes(hit).eval(hit, wo, wi)
+    }
+
+    fn sample(&self, ray: &Ray, hit: &HitRecord, u: (Float, Float)) -> Option<BsdfSample> {
+        let lobes = self.lobes(hit);
+
+        let (lobe, u0) = choose_among(u.0, &lobes.probabilities);
+        let u = (u0, u.1);
+        let scattered = match lobe {
+            0 => Ray::new(hit.point(), &Onb::from_normal(hit.normal()).to_world(&cosine_hemisphere(u))),
+            1 => lobes.specular.sample(ray, hit, u)?.ray,
+            2 => lobes.clearcoat.sample(ray, hit, u)?.ray,
+            _ => lobes.glass.sample(ray, hit, u)?.ray
+        };
+
+        // Weighing by every lobe that could have produced the direction, rather than only the chosen one, keeps variance low
+        let wo = -ray.direction().normalize();
+        let wi = scattered.direction().normalize();
+        let pdf = lobes.pdf(hit, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: (1.0 / pdf) * lobes.eval(hit, &wo, &wi),
+            ray: scattered,
+            pdf,
+            is_delta: false
+        })
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.lobes(hit).pdf(hit, wo, wi)
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.base_color.value(hit)
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{bvh::Bvh, environment::Environment, lights::Light, objects::{HitRecord, Hittable, Hittables}, simd::LANES, vec::{Float, Ray}};
+
+/// Everything a ray can see: the objects, and the surroundings beyond them, along with the lights shining on them
+pub struct Scene {
+    objects: Bvh,
+    environment: Box<dyn Environment + Send + Sync>,
+    lights: Vec<Box<dyn Light + Send + Sync>>,
+}
+
+impl Scene {
+    pub fn new(objects: Hittables, environment: Box<dyn Environment + Send + Sync>) -> Self {
+        Self {
+            objects: Bvh::new(objects),
+            environment,
+            lights: vec![]
+        }
+    }
+
+    pub fn push_light(&mut self, light: Box<dyn Light + Send + Sync>) {
+        self.lights.push(light);
+    }
+
+    pub fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        self.objects.hit(ray, tmin, tmax)
+    }
+
+    /// The closest hit for each of up to `LANES` rays, traced together as a packet
+    pub fn hit_packet(&self, rays: &[Ray], tmin: Float, tmax: Float) -> [Option<HitRecord<'_>>; LANES] {
+        self.objects.hit_packet(rays, tmin, tmax)
+    }
+
+    /// Fraction of light getting through everything along the ray within the range, for shadow rays
+    pub fn transmittance(&self, ray: &Ray, tmin: Float, tmax: Float) -> Float {
+        self.objects.transmittance(ray, tmin, tmax)
+    }
+
+    pub fn environment(&self) -> &(dyn Environment + Send + Sync) {
+        self.environment.as_ref()
+    }
+
+    pub fn lights(&self) -> &[Box<dyn Light + Send + Sync>] {
+        &self.lights
+    }
+}
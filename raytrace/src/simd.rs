@@ -0,0 +1,190 @@
+use crate::vec::{Float, Ray, Vec3};
+use std::ops;
+use wide::{CmpLe, CmpLt};
+
+/// Rays traced together in a packet, enough to fill a 256 bit register at the build's precision
+#[cfg(not(feature = "f32"))]
+pub const LANES: usize = 4;
+#[cfg(feature = "f32")]
+pub const LANES: usize = 8;
+
+#[cfg(not(feature = "f32"))]
+type Vector = wide::f64x4;
+#[cfg(feature = "f32")]
+type Vector = wide::f32x8;
+
+/// One value per lane, held in a vector register where the target has one wide enough and in pairs of narrower ones elsewhere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Floats(Vector);
+
+/// One flag per lane, from comparing `Floats`, as the bits of a number with the first lane lowest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mask(u32);
+
+impl Floats {
+    pub fn new(values: [Float; LANES]) -> Self {
+        Self(Vector::new(values))
+    }
+
+    pub fn splat(value: Float) -> Self {
+        Self(Vector::splat(value))
+    }
+
+    pub fn lane(&self, lane: usize) -> Float {
+        self.0.as_array_ref()[lane]
+    }
+
+    pub fn set_lane(&mut self, lane: usize, value: Float) {
+        self.0.as_array_mut()[lane] = value;
+    }
+
+    /// Lane by lane minimum, ignoring NaN in either side
+    pub fn min(self, other: Self) -> Self {
+        Self(self.0.min(other.0))
+    }
+
+    /// Lane by lane maximum, ignoring NaN in either side
+    pub fn max(self, other: Self) -> Self {
+        Self(self.0.max(other.0))
+    }
+
+    pub fn le(self, other: Self) -> Mask {
+        Mask(self.0.cmp_le(other.0).move_mask() as u32)
+    }
+
+    pub fn lt(self, other: Self) -> Mask {
+        Mask(self.0.cmp_lt(other.0).move_mask() as u32)
+    }
+}
+
+impl Mask {
+    pub fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn lane(self, lane: usize) -> bool {
+        self.0 & (1 << lane) != 0
+    }
+
+    /// The first lane that is set, if any
+    pub fn first(self) -> Option<usize> {
+        if self.any() { Some(self.0.trailing_zeros() as usize) } else { None }
+    }
+}
+
+impl ops::Add for Floats {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl ops::Sub for Floats {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl ops::Mul for Floats {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(self.0 * rhs.0)
+    }
+}
+
+impl ops::Div for Floats {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        Self(self.0 / rhs.0)
+    }
+}
+
+/// A vector per lane, stored as one `Floats` per axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec3s {
+    pub x: Floats,
+    pub y: Floats,
+    pub z: Floats,
+}
+
+impl Vec3s {
+    /// Gathers up to `LANES` vectors, repeating the last one to fill the rest
+    pub fn gather(vs: &[Vec3]) -> Self {
+        assert!(!vs.is_empty() && vs.len() <= LANES, "A packet holds between 1 and LANES vectors");
+        let at = |i: usize| vs[i.min(vs.len() - 1)];
+        Self {
+            x: Floats::new(std::array::from_fn(|i| at(i).0)),
+            y: Floats::new(std::array::from_fn(|i| at(i).1)),
+            z: Floats::new(std::array::from_fn(|i| at(i).2))
+        }
+    }
+
+    pub fn axis(&self, axis: usize) -> Floats {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => panic!("Vec3s axis out of range: {}", axis)
+        }
+    }
+}
+
+/// Rays traced together, such as camera rays through nearby points, which tend to visit the same parts of a scene
+pub struct RayPacket<'a> {
+    rays: &'a [Ray],
+    origins: Vec3s,
+    inv_directions: Vec3s,
+}
+
+impl<'a> RayPacket<'a> {
+    pub fn new(rays: &'a [Ray]) -> Self {
+        let origins: Vec<Vec3> = rays.iter().map(|r| *r.origin()).collect();
+        let directions: Vec<Vec3> = rays.iter().map(|r| *r.direction()).collect();
+        let directions = Vec3s::gather(&directions);
+        let one = Floats::splat(1.0);
+        Self {
+            rays,
+            origins: Vec3s::gather(&origins),
+            inv_directions: Vec3s {
+                x: one / directions.x,
+                y: one / directions.y,
+                z: one / directions.z
+            }
+        }
+    }
+
+    pub fn rays(&self) -> &'a [Ray] {
+        self.rays
+    }
+
+    /// Lanes that hold one of the packet's rays, rather than padding
+    pub fn active(&self) -> Mask {
+        Floats::new(std::array::from_fn(|i| i as Float)).lt(Floats::splat(self.rays.len() as Float))
+    }
+
+    /// Which rays pass through the box between `tmin` and their own `tmax`
+    pub fn overlaps(&self, min: &Vec3, max: &Vec3, tmin: Floats, tmax: Floats) -> Mask {
+        let mut enter = tmin;
+        let mut exit = tmax;
+        for axis in 0..3 {
+            let origin = self.origins.axis(axis);
+            let inv = self.inv_directions.axis(axis);
+            let t0 = (Floats::splat(min[axis]) - origin) * inv;
+            let t1 = (Floats::splat(max[axis]) - origin) * inv;
+            // Rays parallel to a flat box give NaN here, which min and max skip over
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        enter.le(exit)
+    }
+
+    /// Whether the first active ray travels towards negative values on an axis
+    pub fn is_negative(&self, axis: usize, active: Mask) -> bool {
+        self.inv_directions.axis(axis).lane(active.first().unwrap_or(0)) < 0.0
+    }
+}
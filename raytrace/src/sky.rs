@@ -0,0 +1,149 @@
+use crate::{environment::Environment, spectrum::xyz_to_linear_srgb, util::*, vec::{consts::{FRAC_PI_2, PI}, Color, Float, Onb, Vec3}};
+
+// Angular radius of the sun as seen from the ground
+const SUN_RADIUS: Float = 0.004654;
+// Luminance of the sun before the atmosphere dims it, in kcd/m²
+const SUN_LUMINANCE: Float = 2.0e6;
+// Chance of sampling the sun rather than the rest of the sky, while it is up
+const SUN_SAMPLE_CHANCE: Float = 0.5;
+
+/// The Perez et al. luminance distribution, with its five coefficients
+struct Perez([Float; 5]);
+
+impl Perez {
+    /// Relative brightness at `theta` from the zenith and `gamma` from the sun
+    fn at(&self, theta: Float, gamma: Float) -> Float {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / theta.cos().max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+/// A clear daytime sky by the Preetham et al. 1999 model, lit by the sun it contains.
+/// Radiance is in kcd/m² times the intensity, whose default exposes a white surface in full sun to about 1.
+pub struct Sky {
+    sun_direction: Vec3,
+    intensity: Float,
+    // Y, x and y distributions, and their values at the zenith
+    distributions: [Perez; 3],
+    zenith: [Float; 3],
+    sun_radiance: Color,
+}
+
+impl Sky {
+    /// `turbidity` is how hazy the air is, from 2 for a very clear day to around 10 for a hazy one
+    pub fn new(sun_direction: Vec3, turbidity: Float) -> Self {
+        assert!((1.7..=10.0).contains(&turbidity), "The sky model only covers turbidities from 1.7 to 10");
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity;
+        let theta_s = sun_direction.1.clamp(-1.0, 1.0).acos().min(FRAC_PI_2);
+
+        let distributions = [
+            Perez([0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703]),
+            Perez([-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452]),
+            Perez([-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [Float; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        Self {
+            sun_radiance: SUN_LUMINANCE * sun_transmittance(theta_s, t),
+            sun_direction,
+            intensity: 0.02,
+            distributions,
+            zenith: [luminance.max(0.0), x, y]
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> Self {
+        assert!(intensity >= 0.0, "Sky intensity must not be negative");
+        self.intensity = intensity;
+        self
+    }
+
+    fn is_sun_up(&self) -> bool {
+        self.sun_direction.1 > 0.0
+    }
+
+    /// Whether a direction points at the sun's disk
+    fn in_sun(&self, direction: &Vec3) -> bool {
+        self.is_sun_up() && direction.dot(&self.sun_direction) >= SUN_RADIUS.cos()
+    }
+
+    fn sky_radiance(&self, direction: &Vec3) -> Color {
+        // The model stops at the horizon, so the ground sees the horizon's color
+        let theta = direction.1.clamp(0.0, 1.0).acos();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun_direction.1.clamp(0.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let perez = &self.distributions[i];
+            self.zenith[i] * perez.at(theta, gamma) / perez.at(0.0, theta_s)
+        });
+        let xyz = Vec3(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_linear_srgb(&xyz);
+        Vec3(rgb.0.max(0.0), rgb.1.max(0.0), rgb.2.max(0.0))
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = direction.normalize();
+        let mut radiance = self.sky_radiance(&direction);
+        if self.in_sun(&direction) {
+            radiance = radiance + self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    fn sample(&self, u: (Float, Float)) -> (Vec3, Float) {
+        let chance = if self.is_sun_up() { SUN_SAMPLE_CHANCE } else { 0.0 };
+        let (sun_chosen, u0) = choose(u.0, chance);
+        let u = (u0, u.1);
+        let direction = if sun_chosen {
+            // Uniformly within the cone the sun's disk fills
+            let cos_theta = 1.0 - u.0 * (1.0 - SUN_RADIUS.cos());
+            let sin_theta = (1.0 - cos_theta.powi(2)).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.1;
+            Onb::from_normal(&self.sun_direction).to_world(&Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+        } else {
+            uniform_sphere(u)
+        };
+        let pdf = self.pdf(&direction);
+        (direction, pdf)
+    }
+
+    fn pdf(&self, direction: &Vec3) -> Float {
+        let direction = direction.normalize();
+        let uniform = 1.0 / (4.0 * PI);
+        if !self.is_sun_up() {
+            uniform
+        } else if self.in_sun(&direction) {
+            // The disk is sampled uniformly over the solid angle it covers
+            let sun = 1.0 / (2.0 * PI * (1.0 - SUN_RADIUS.cos()));
+            SUN_SAMPLE_CHANCE * sun + (1.0 - SUN_SAMPLE_CHANCE) * uniform
+        } else {
+            (1.0 - SUN_SAMPLE_CHANCE) * uniform
+        }
+    }
+}
+
+/// Fraction of sunlight reaching the ground in each channel, from Rayleigh and aerosol scattering along the way.
+/// Follows the appendix of Preetham et al., evaluated at a wavelength representative of each channel.
+fn sun_transmittance(theta_s: Float, turbidity: Float) -> Color {
+    // Relative optical mass, the length of the path through the air compared to straight up
+    let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |micrometers: Float| {
+        let rayleigh = 0.008735 * micrometers.powf(-4.08);
+        let aerosol = beta * micrometers.powf(-1.3);
+        (-mass * (rayleigh + aerosol)).exp()
+    };
+    Vec3(channel(0.680), channel(0.550), channel(0.440))
+}
@@ -0,0 +1,100 @@
+use crate::vec::{Color, Float, Vec3};
+use std::sync::OnceLock;
+
+/// Range of wavelengths sampled, in nanometers
+pub const LAMBDA_MIN: Float = 380.0;
+pub const LAMBDA_MAX: Float = 720.0;
+
+/// Wavelength used for anything that is dispersive when not rendering spectrally, the sodium D line
+pub const LAMBDA_DEFAULT: Float = 587.6;
+
+/// Picks a wavelength from a uniform random number, returning it with its pdf
+pub fn sample_wavelength(u: Float) -> (Float, Float) {
+    (LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN), 1.0 / (LAMBDA_MAX - LAMBDA_MIN))
+}
+
+// Smits 1999, "An RGB to Spectrum Conversion for Reflectances", in 10 equal bins from LAMBDA_MIN to LAMBDA_MAX
+const WHITE: [Float; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [Float; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [Float; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [Float; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [Float; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [Float; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [Float; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Value at `wavelength` of a smooth spectrum matching an RGB color
+pub fn rgb_to_spectrum(color: &Color, wavelength: Float) -> Float {
+    let bin = (((wavelength - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0) as usize).min(9);
+    let (r, g, b) = (color.0, color.1, color.2);
+
+    // The shared part of all channels is white, the rest is made of one secondary and one primary color
+    if r <= g && r <= b {
+        r * WHITE[bin] + if g <= b {
+            (g - r) * CYAN[bin] + (b - g) * BLUE[bin]
+        } else {
+            (b - r) * CYAN[bin] + (g - b) * GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        g * WHITE[bin] + if r <= b {
+            (r - g) * MAGENTA[bin] + (b - r) * BLUE[bin]
+        } else {
+            (b - g) * MAGENTA[bin] + (r - b) * RED[bin]
+        }
+    } else {
+        b * WHITE[bin] + if r <= g {
+            (r - b) * YELLOW[bin] + (g - r) * GREEN[bin]
+        } else {
+            (g - b) * YELLOW[bin] + (r - g) * RED[bin]
+        }
+    }
+}
+
+/// Replaces an RGB color with its value at the ray's wavelength in every channel, if the ray has one
+pub fn project(color: Color, wavelength: Option<Float>) -> Color {
+    match wavelength {
+        Some(wavelength) => {
+            let value = rgb_to_spectrum(&color, wavelength);
+            Vec3(value, value, value)
+        },
+        None => color
+    }
+}
+
+/// CIE 1931 color matching functions.
+/// Wyman et al. 2013, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(wavelength: Float) -> Vec3 {
+    let g = |mu: Float, sigma_low: Float, sigma_high: Float| {
+        let sigma = if wavelength < mu { sigma_low } else { sigma_high };
+        (-0.5 * ((wavelength - mu) / sigma).powi(2)).exp()
+    };
+    Vec3(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Color {
+    Vec3(
+        3.2404542 * xyz.0 - 1.5371385 * xyz.1 - 0.4985314 * xyz.2,
+        -0.9692660 * xyz.0 + 1.8760108 * xyz.1 + 0.0415560 * xyz.2,
+        0.0556434 * xyz.0 - 0.2040259 * xyz.1 + 1.0572252 * xyz.2
+    )
+}
+
+/// Linear sRGB of a constant spectrum of 1, used to keep white surfaces white
+fn white_point() -> &'static Color {
+    static WHITE_POINT: OnceLock<Color> = OnceLock::new();
+    WHITE_POINT.get_or_init(|| {
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        let xyz: Vec3 = (0..steps).map(|i| cie_xyz(LAMBDA_MIN + i as Float + 0.5)).sum();
+        xyz_to_linear_srgb(&xyz)
+    })
+}
+
+/// Converts the radiance carried by a single wavelength, sampled with the given pdf, into an estimate of its RGB color
+pub fn to_rgb(radiance: Float, wavelength: Float, pdf: Float) -> Color {
+    let rgb = xyz_to_linear_srgb(&((radiance / pdf) * cie_xyz(wavelength)));
+    let white = white_point();
+    Vec3(rgb.0 / white.0, rgb.1 / white.1, rgb.2 / white.2)
+}
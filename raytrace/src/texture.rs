@@ -0,0 +1,256 @@
+use crate::{objects::HitRecord, util::srgb_to_linear, vec::{Color, Float, Vec3}};
+use std::{path::Path, sync::Arc};
+
+/// A value that varies over a surface.
+/// Scalar parameters use the first channel.
+pub trait Texture {
+    fn value(&self, hit: &HitRecord) -> Color;
+
+    fn scalar(&self, hit: &HitRecord) -> Float {
+        self.value(hit).0
+    }
+}
+
+impl Texture for Color {
+    fn value(&self, _hit: &HitRecord) -> Color {
+        *self
+    }
+}
+
+impl Texture for Float {
+    fn value(&self, _hit: &HitRecord) -> Color {
+        Vec3(*self, *self, *self)
+    }
+}
+
+/// Alternates between two textures in a 3d grid of cubes
+pub struct Checker {
+    even: Arc<dyn Texture + Send + Sync>,
+    odd: Arc<dyn Texture + Send + Sync>,
+    size: Float,
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture + Send + Sync>, odd: Arc<dyn Texture + Send + Sync>, size: Float) -> Self {
+        assert!(size > 0.0, "Checker squares must have a positive size");
+        Self {
+            even,
+            odd,
+            size
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, hit: &HitRecord) -> Color {
+        let p = hit.point();
+        let cell = (p.0 / self.size).floor() + (p.1 / self.size).floor() + (p.2 / self.size).floor();
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(hit)
+        } else {
+            self.odd.value(hit)
+        }
+    }
+}
+
+/// How an `ImageTexture` averages texels over the footprint of a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Blends the nearest four texels of the full size image, ignoring the footprint
+    Bilinear,
+    /// Blends between the two mip levels closest to the footprint's size, treating it as round
+    Trilinear,
+    /// Weighs texels by an elliptical Gaussian fitted to the footprint, staying sharp along surfaces seen at an angle
+    Ewa,
+}
+
+// Footprints more stretched than this are widened, so that EWA filtering visits a bounded number of texels
+const MAX_ANISOTROPY: Float = 8.0;
+// Falloff of the Gaussian used by EWA filtering, over the ellipse's radius
+const EWA_ALPHA: Float = 2.0;
+
+/// One level of a mip pyramid
+struct Level {
+    width: usize,
+    height: usize,
+    // Linear, row by row from the top
+    pixels: Vec<Color>,
+}
+
+impl Level {
+    fn texel(&self, x: isize, y: isize) -> Color {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinearly filtered value at texture coordinates, with V pointing up the image
+    fn bilinear(&self, (u, v): (Float, Float)) -> Color {
+        let x = u * self.width as Float - 0.5;
+        let y = (1.0 - v) * self.height as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+
+    /// Half the size on each axis, averaging each 2 by 2 block, or a column or row of 2 once one side reaches 1
+    fn downsample(&self) -> Self {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| {
+            let xs = [2 * x, (2 * x + 1).min(self.width - 1)];
+            let ys = [2 * y, (2 * y + 1).min(self.height - 1)];
+            0.25 * ys.iter().flat_map(|&y| xs.iter().map(move |&x| self.pixels[y * self.width + x])).sum::<Color>()
+        }).collect();
+        Self {
+            width,
+            height,
+            pixels
+        }
+    }
+
+    /// Gaussian weighted average over the ellipse with axes `a` and `b` around a point, all in texture coordinates
+    fn ewa(&self, (u, v): (Float, Float), a: (Float, Float), b: (Float, Float)) -> Color {
+        // In texels, with Y running down the image
+        let (w, h) = (self.width as Float, self.height as Float);
+        let (s, t) = (u * w - 0.5, (1.0 - v) * h - 0.5);
+        let (a, b) = ((a.0 * w, -a.1 * h), (b.0 * w, -b.1 * h));
+
+        // The implicit ellipse A s² + B s t + C t² < F, grown by a texel so that it never falls between texels
+        let coef_a = a.1 * a.1 + b.1 * b.1 + 1.0;
+        let coef_b = -2.0 * (a.0 * a.1 + b.0 * b.1);
+        let coef_c = a.0 * a.0 + b.0 * b.0 + 1.0;
+        let f = coef_a * coef_c - 0.25 * coef_b * coef_b;
+        let (coef_a, coef_b, coef_c) = (coef_a / f, coef_b / f, coef_c / f);
+
+        // Bounding box of the ellipse
+        let det = 4.0 * coef_a * coef_c - coef_b * coef_b;
+        let s_radius = 2.0 * (coef_c / det).sqrt();
+        let t_radius = 2.0 * (coef_a / det).sqrt();
+        let (s0, s1) = ((s - s_radius).ceil() as isize, (s + s_radius).floor() as isize);
+        let (t0, t1) = ((t - t_radius).ceil() as isize, (t + t_radius).floor() as isize);
+
+        let mut sum = Vec3(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+        for y in t0..=t1 {
+            let dt = y as Float - t;
+            for x in s0..=s1 {
+                let ds = x as Float - s;
+                let r2 = coef_a * ds * ds + coef_b * ds * dt + coef_c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum = sum + weight * self.texel(x, y);
+                    total += weight;
+                }
+            }
+        }
+        if total > 0.0 { sum / total } else { self.bilinear((u, v)) }
+    }
+}
+
+/// An image wrapped over a surface's texture coordinates, repeating outside of [0, 1].
+/// Keeps a mip pyramid so that it can be filtered over the footprint of the ray that hit it.
+pub struct ImageTexture {
+    // From the full size image down to a single texel
+    levels: Vec<Level>,
+    filter: Filter,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "Image textures must not be empty");
+        assert_eq!(pixels.len(), width * height, "Image textures must have one pixel per texel");
+        let mut levels = vec![Level { width, height, pixels }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self {
+            levels,
+            filter: Filter::Trilinear
+        }
+    }
+
+    /// Loads a color image, converting it from sRGB to linear
+    pub fn load<P: AsRef<Path>>(path: P) -> image::ImageResult<Self> {
+        Self::decode(path, srgb_to_linear)
+    }
+
+    /// Loads an image whose values are already linear, such as a normal or height map
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> image::ImageResult<Self> {
+        Self::decode(path, |c| c)
+    }
+
+    fn decode<P: AsRef<Path>>(path: P, to_linear: fn(Float) -> Float) -> image::ImageResult<Self> {
+        let image = image::open(path)?.to_rgb8();
+        let pixels = image.pixels()
+            .map(|p| Vec3(to_linear(p[0] as Float / 255.0), to_linear(p[1] as Float / 255.0), to_linear(p[2] as Float / 255.0)))
+            .collect();
+        Ok(Self::new(image.width() as usize, image.height() as usize, pixels))
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Bilinearly filtered value of the full size image at texture coordinates, with V pointing up the image
+    pub fn sample(&self, uv: (Float, Float)) -> Color {
+        self.levels[0].bilinear(uv)
+    }
+
+    /// Value averaged over a footprint, given by how far the texture coordinates move across a pixel in X and in Y
+    pub fn sample_filtered(&self, uv: (Float, Float), duv_dx: (Float, Float), duv_dy: (Float, Float)) -> Color {
+        let length = |d: (Float, Float)| (d.0 * d.0 + d.1 * d.1).sqrt();
+        match self.filter {
+            Filter::Bilinear => self.sample(uv),
+            Filter::Trilinear => {
+                let width = 2.0 * duv_dx.0.abs().max(duv_dx.1.abs()).max(duv_dy.0.abs()).max(duv_dy.1.abs());
+                self.trilinear(uv, width)
+            },
+            Filter::Ewa => {
+                let (major, mut minor) = if length(duv_dx) >= length(duv_dy) { (duv_dx, duv_dy) } else { (duv_dy, duv_dx) };
+                let (major_length, mut minor_length) = (length(major), length(minor));
+                if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+                    let scale = major_length / (minor_length * MAX_ANISOTROPY);
+                    minor = (minor.0 * scale, minor.1 * scale);
+                    minor_length *= scale;
+                }
+                if minor_length == 0.0 {
+                    return self.trilinear(uv, 2.0 * major_length);
+                }
+
+                // The level where the minor axis spans about a texel, so that the ellipse covers few texels
+                let level = (self.levels.len() as Float - 1.0 + minor_length.max(1e-8).log2()).max(0.0);
+                let lower = level.floor() as usize;
+                if lower + 1 >= self.levels.len() {
+                    return self.levels[self.levels.len() - 1].pixels[0];
+                }
+                let fraction = level - lower as Float;
+                (1.0 - fraction) * self.levels[lower].ewa(uv, major, minor) + fraction * self.levels[lower + 1].ewa(uv, major, minor)
+            }
+        }
+    }
+
+    /// Blends the two levels whose texels are closest to `width` across, in texture coordinates
+    fn trilinear(&self, uv: (Float, Float), width: Float) -> Color {
+        // The last level is a single texel spanning the whole of [0, 1]
+        let level = (self.levels.len() as Float - 1.0 + width.max(1e-8).log2()).max(0.0);
+        let lower = level.floor() as usize;
+        if lower + 1 >= self.levels.len() {
+            return self.levels[self.levels.len() - 1].pixels[0];
+        }
+        let fraction = level - lower as Float;
+        (1.0 - fraction) * self.levels[lower].bilinear(uv) + fraction * self.levels[lower + 1].bilinear(uv)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, hit: &HitRecord) -> Color {
+        let (duv_dx, duv_dy) = hit.uv_derivatives();
+        self.sample_filtered(hit.uv(), duv_dx, duv_dy)
+    }
+}
@@ -1,12 +1,7 @@
-use crate::Vec3;
+use crate::vec::{consts::TAU, Float, Vec3};
 use rand::Rng;
 
-// TAU IS UNSTABLE OR SOMETHING
-// Note: check back in a few days it might be less unstable
-// except actually it apparently misses the release cycle by a couple days
-const TAU: f64 = 6.28318530717958647692528676655900577f64;
-
-pub fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+pub fn lerp(a: Vec3, b: Vec3, t: Float) -> Vec3 {
     (1.0 - t) * a + t * b
 }
 
@@ -18,7 +13,7 @@ pub fn sphere_to_cartesian(sphere: Vec3) -> Vec3 {
     )
 }
 
-pub fn random_sphere_point(radius: f64) -> Vec3 {
+pub fn random_sphere_point(radius: Float) -> Vec3 {
     if radius.abs() <= 0.0000001 {
         Vec3(0.0, 0.0, 0.0)        
     } else {
@@ -33,7 +28,7 @@ pub fn random_sphere_point(radius: f64) -> Vec3 {
     }
 }
 
-pub fn random_disk_vec(radius: f64) -> Vec3 {
+pub fn random_disk_vec(radius: Float) -> Vec3 {
     if radius.abs() <= 0.0000001 {
         Vec3(0.0, 0.0, 0.0)
     } else {
@@ -53,7 +48,7 @@ pub fn random_disk_vec(radius: f64) -> Vec3 {
 pub fn random_unit_vector() -> Vec3 {
     let mut rng = rand::thread_rng();
     let a = rng.gen_range(0.0, TAU);
-    let z: f64 = rng.gen_range(-1.0, 1.0);
+    let z: Float = rng.gen_range(-1.0, 1.0);
     let r = (1.0 - z.powi(2)).sqrt();
     Vec3(r*a.cos(), r*a.sin(), z)
 }
@@ -62,14 +57,101 @@ pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     v - 2.0 * v.dot(n) * n
 }
 
-pub fn refract(uv: &Vec3, normal: &Vec3, etai_etat: f64) -> Vec3 {
-    let cos_theta = (-uv).dot(&normal);
+pub fn refract(uv: &Vec3, normal: &Vec3, etai_etat: Float) -> Vec3 {
+    let cos_theta = (-uv).dot(normal);
     let parallel = etai_etat * (uv + cos_theta * normal);
     let perp = -(1.0 - parallel.length_squared()).sqrt() * normal;
     parallel + perp
 }
 
-pub fn schlick(cos: f64, idx: f64) -> f64 {
+pub fn schlick(cos: Float, idx: Float) -> Float {
     let r0 = ((1.0-idx) / (1.0+idx)).powi(2);
     r0 + (1.0-r0)*(1.0-cos).powi(5)
-}
\ No newline at end of file
+}
+
+/// Exact Fresnel reflectance of unpolarized light at a dielectric boundary.
+/// `eta` is the refractive index on the far side divided by the index on the incident side.
+pub fn fresnel_dielectric(cos_i: Float, eta: Float) -> Float {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i.powi(2)) / eta.powi(2);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs.powi(2) + rp.powi(2)) / 2.0
+}
+
+/// Exact Fresnel reflectance of unpolarized light on a conductor with complex refractive index `eta + ik`
+pub fn fresnel_conductor(cos_i: Float, eta: Float, k: Float) -> Float {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta.powi(2) - k.powi(2) - sin2;
+    let a2b2 = (t0.powi(2) + 4.0 * eta.powi(2) * k.powi(2)).sqrt();
+    let t1 = a2b2 + cos2;
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2.powi(2);
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+/// Whether a uniform random number falls below `chance`, along with the number stretched back over [0, 1) from the side it fell on,
+/// so that one number can both make a choice and go on to sample what was chosen
+pub fn choose(u: Float, chance: Float) -> (bool, Float) {
+    if u < chance {
+        (true, u / chance)
+    } else {
+        (false, ((u - chance) / (1.0 - chance)).min(1.0 - Float::EPSILON))
+    }
+}
+
+/// Which of several outcomes a uniform random number picks, given their probabilities summing to one,
+/// along with the number stretched back over [0, 1) as `choose` does
+pub fn choose_among(mut u: Float, probabilities: &[Float]) -> (usize, Float) {
+    let last = probabilities.len() - 1;
+    let mut remaining = 1.0;
+    for (index, &probability) in probabilities[..last].iter().enumerate() {
+        // Each choice is between this outcome and the ones after it
+        let (chosen, rest) = choose(u, probability / remaining);
+        if chosen {
+            return (index, rest);
+        }
+        u = rest;
+        remaining -= probability;
+    }
+    (last, u)
+}
+
+/// Cosine weighted direction around +Z from two uniform random numbers
+pub fn cosine_hemisphere(u: (Float, Float)) -> Vec3 {
+    let r = u.0.sqrt();
+    let phi = TAU * u.1;
+    Vec3(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+/// Uniformly distributed unit vector from two uniform random numbers
+pub fn uniform_sphere(u: (Float, Float)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z.powi(2)).max(0.0).sqrt();
+    let phi = TAU * u.1;
+    Vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Relative luminance of a linear color
+pub fn luminance(c: &Vec3) -> Float {
+    0.2126 * c.0 + 0.7152 * c.1 + 0.0722 * c.2
+}
+
+/// Decodes an sRGB encoded value in [0, 1]
+pub fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -2,18 +2,30 @@ use std::ops;
 
 use rand::Rng;
 
-#[derive(Debug, PartialEq)]
-pub struct Vec3(pub f64, pub f64, pub f64);
+/// Precision of positions, directions and colors, which is `f32` when built with the `f32` feature
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+/// Mathematical constants at the precision of `Float`
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec3(pub Float, pub Float, pub Float);
 
 pub type Color = Vec3;
 pub type Point = Vec3;
 
 impl Vec3 {
-    pub fn length_squared(&self) -> f64 {
+    pub fn length_squared(&self) -> Float {
         self.0.powi(2) + self.1.powi(2) + self.2.powi(2)
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         self.length_squared().sqrt()
     }
 
@@ -26,7 +38,7 @@ impl Vec3 {
         }
     }
 
-    pub fn dot(&self, other: &Vec3) -> f64 {
+    pub fn dot(&self, other: &Vec3) -> Float {
         self.0 * other.0 + self.1 * other.1 + self.2 * other.2
     }
 
@@ -35,114 +47,94 @@ impl Vec3 {
     }
 }
 
-impl ops::Neg for Vec3 {
-    type Output = Vec3;
-    fn neg(self) -> Self::Output {
-        Vec3(-self.0, -self.1, -self.2)
-    }
-}
-
-impl ops::Neg for &Vec3 {
-    type Output = Vec3;
-    fn neg(self) -> Self::Output {
-        Vec3(-self.0, -self.1, -self.2)
-    }
-}
-
-impl ops::Add for Vec3 {
-    type Output = Vec3;
-    fn add(self, rhs: Self) -> Self::Output {
-        Vec3(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+impl ops::Index<usize> for Vec3 {
+    type Output = Float;
+    fn index(&self, idx: usize) -> &Self::Output {
+        match idx {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of range: {}", idx)
+        }
     }
 }
 
-impl ops::Add<Vec3> for &Vec3 {
-    type Output = Vec3;
-    fn add(self, rhs: Vec3) -> Self::Output {
-        Vec3(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
-    }
-}
+/// Implements an element wise operator for every mix of owned and borrowed vectors
+macro_rules! element_wise {
+    ($op:ident, $method:ident, $symbol:tt) => {
+        impl ops::$op for Vec3 {
+            type Output = Vec3;
+            fn $method(self, rhs: Vec3) -> Self::Output {
+                Vec3(self.0 $symbol rhs.0, self.1 $symbol rhs.1, self.2 $symbol rhs.2)
+            }
+        }
 
-impl ops::Add for &Vec3 {
-    type Output = Vec3;
-    fn add(self, rhs: Self) -> Self::Output {
-        Vec3(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
-    }
-}
+        impl ops::$op<&Vec3> for Vec3 {
+            type Output = Vec3;
+            fn $method(self, rhs: &Vec3) -> Self::Output {
+                ops::$op::$method(self, *rhs)
+            }
+        }
 
-impl ops::Add<&Vec3> for Vec3 {
-    type Output = Vec3;
-    fn add(self, rhs: &Vec3) -> Self::Output {
-        Vec3(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
-    }
-}
+        impl ops::$op<Vec3> for &Vec3 {
+            type Output = Vec3;
+            fn $method(self, rhs: Vec3) -> Self::Output {
+                ops::$op::$method(*self, rhs)
+            }
+        }
 
-impl ops::Sub for Vec3 {
-    type Output = Vec3;
-    fn sub(self, rhs: Self) -> Self::Output {
-        Vec3(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
-    }
+        impl ops::$op for &Vec3 {
+            type Output = Vec3;
+            fn $method(self, rhs: &Vec3) -> Self::Output {
+                ops::$op::$method(*self, *rhs)
+            }
+        }
+    };
 }
 
-impl ops::Sub<&Vec3> for Vec3 {
-    type Output = Vec3;
-    fn sub(self, rhs: &Vec3) -> Self::Output {
-        Vec3(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
-    }
-}
+element_wise!(Add, add, +);
+element_wise!(Sub, sub, -);
+element_wise!(Mul, mul, *);
 
-impl ops::Sub for &Vec3 {
+impl ops::Neg for Vec3 {
     type Output = Vec3;
-    fn sub(self, rhs: Self) -> Self::Output {
-        Vec3(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    fn neg(self) -> Self::Output {
+        Vec3(-self.0, -self.1, -self.2)
     }
 }
 
-impl ops::Sub<Vec3> for &Vec3 {
+impl ops::Neg for &Vec3 {
     type Output = Vec3;
-    fn sub(self, rhs: Vec3) -> Self::Output {
-        Vec3(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
-    }
-}
-
-impl ops::Mul for Vec3 {
-    type Output = Self;
-    fn mul(self, rhs: Self) -> Self::Output {
-        Self(self.0 * rhs.0, self.1 * rhs.1, self.2 * rhs.2)
+    fn neg(self) -> Self::Output {
+        -*self
     }
 }
 
-impl ops::Mul<Vec3> for f64 {
+impl ops::Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: Vec3) -> Self::Output {
         Vec3(self * rhs.0, self * rhs.1, self * rhs.2)
     }
 }
 
-impl ops::Mul<&Vec3> for f64 {
+impl ops::Mul<&Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: &Vec3) -> Self::Output {
-        Vec3(self * rhs.0, self * rhs.1, self * rhs.2)
+        self * *rhs
     }
 }
 
-impl ops::Div<f64> for Vec3 {
+impl ops::Div<Float> for Vec3 {
     type Output = Vec3;
-    fn div(self, rhs: f64) -> Self::Output {
+    fn div(self, rhs: Float) -> Self::Output {
         Vec3(self.0 / rhs, self.1 / rhs, self.2 / rhs)
     }
 }
 
-impl ops::Div<f64> for &Vec3 {
+impl ops::Div<Float> for &Vec3 {
     type Output = Vec3;
-    fn div(self, rhs: f64) -> Self::Output {
-        Vec3(self.0 / rhs, self.1 / rhs, self.2 / rhs)
-    }
-}
-
-impl std::clone::Clone for Vec3 {
-    fn clone(&self) -> Vec3 {
-        Vec3(self.0, self.1, self.2)
+    fn div(self, rhs: Float) -> Self::Output {
+        *self / rhs
     }
 }
 
@@ -156,13 +148,13 @@ impl Color {
     pub fn random() -> Color {
         let mut rng = rand::thread_rng();
         Vec3(
-            rng.gen::<f64>(),
-            rng.gen::<f64>(),
-            rng.gen::<f64>()
+            rng.gen::<Float>(),
+            rng.gen::<Float>(),
+            rng.gen::<Float>()
         )
     }
 
-    pub fn rand_range(low: f64, high: f64) -> Color {
+    pub fn rand_range(low: Float, high: Float) -> Color {
         let mut rng = rand::thread_rng();
         Vec3(
             rng.gen_range(low, high),
@@ -172,29 +164,92 @@ impl Color {
     }
 }
 
+/// Rays one pixel over in X and in Y from a camera ray, which track how much of the scene the ray's pixel covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayDifferential {
+    pub x_origin: Point,
+    pub x_direction: Vec3,
+    pub y_origin: Point,
+    pub y_direction: Vec3,
+}
+
 #[derive(Debug)]
 pub struct Ray {
     origin: Point,
     direction: Vec3,
+    // In nanometers, for rays carrying a single wavelength when rendering spectrally
+    wavelength: Option<Float>,
+    differential: Option<RayDifferential>,
 }
 
 impl Ray {
     pub fn new(origin: &Point, direction: &Vec3) -> Self {
         Self {
-            origin: origin.clone(),
-            direction: direction.clone()
+            origin: *origin,
+            direction: *direction,
+            wavelength: None,
+            differential: None
         }
     }
 
-    pub fn at(&self, t: f64) -> Point {
-        &self.origin + t * &self.direction
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
+
+    /// Neighbouring rays for working out the ray's footprint, which only camera rays and their specular bounces carry
+    pub fn differential(&self) -> Option<&RayDifferential> {
+        self.differential.as_ref()
     }
 
-    pub fn origin<'a>(&'a self) -> &'a Point {
+    pub fn with_wavelength(mut self, wavelength: Option<Float>) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
+
+    pub fn wavelength(&self) -> Option<Float> {
+        self.wavelength
+    }
+
+    pub fn at(&self, t: Float) -> Point {
+        self.origin + t * self.direction
+    }
+
+    pub fn origin(&self) -> &Point {
         &self.origin
     }
 
-    pub fn direction<'a>(&'a self) -> &'a Vec3 {
+    pub fn direction(&self) -> &Vec3 {
         &self.direction
     }
 }
+
+/// Orthonormal basis around a normal, for working in a surface's local space where the normal is +Z
+#[derive(Debug, Clone)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn from_normal(normal: &Vec3) -> Self {
+        // Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+        let sign = Float::copysign(1.0, normal.2);
+        let a = -1.0 / (sign + normal.2);
+        let b = normal.0 * normal.1 * a;
+        Self {
+            u: Vec3(1.0 + sign * normal.0.powi(2) * a, sign * b, -sign * normal.0),
+            v: Vec3(b, sign + normal.1.powi(2) * a, -normal.1),
+            w: *normal
+        }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3(v.dot(&self.u), v.dot(&self.v), v.dot(&self.w))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.0 * self.u + v.1 * self.v + v.2 * self.w
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{Ray, materials::Material, objects::{Aabb, Hittable, HitRecord}, vec::{Float, Point, Vec3}};
+use std::{fs, io, path::Path, sync::Arc};
+
+/// A volume of uniform density filling a closed boundary, such as smoke or fog
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    density: Float,
+    phase: Arc<dyn Material + Send + Sync>,
+}
+
+impl ConstantMedium {
+    /// `density` is the chance per unit distance for a ray to scatter.
+    /// The phase function, usually `Isotropic`, decides where scattered rays go.
+    pub fn new(boundary: Box<dyn Hittable + Send + Sync>, density: Float, phase: Arc<dyn Material + Send + Sync>) -> Self {
+        assert!(density > 0.0, "Density of a medium must be positive");
+        // Open surfaces have no inside to fill, so the medium would never scatter
+        assert!(boundary.is_closed(), "Media need a closed boundary, such as a sphere, cuboid or closed mesh");
+        Self {
+            boundary,
+            density,
+            phase
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let ray_length = ray.direction().length();
+        // Free flight distances are memoryless, so one sample can be spent across every span inside the boundary
+        let mut remaining = -rand::random::<Float>().ln() / self.density;
+
+        for interval in self.boundary.intervals(ray) {
+            let enter = interval.enter.t.max(tmin);
+            let exit = interval.exit.t.min(tmax);
+            if enter >= exit {
+                continue;
+            }
+
+            let inside = (exit - enter) * ray_length;
+            if remaining < inside {
+                let t = enter + remaining / ray_length;
+                // Media have no surface, so any normal facing the ray will do
+                return Some(HitRecord::new(ray, t, -ray.direction().normalize(), self.phase.as_ref()));
+            }
+            remaining -= inside;
+        }
+
+        None
+    }
+
+    /// Uniform density lets the fraction getting through be found exactly, from the distance spent inside
+    fn transmittance(&self, ray: &Ray, tmin: Float, tmax: Float) -> Float {
+        let inside: Float = self.boundary.intervals(ray).iter()
+            .map(|interval| (interval.exit.t.min(tmax) - interval.enter.t.max(tmin)).max(0.0))
+            .sum();
+        (-self.density * inside * ray.direction().length()).exp()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+/// A volume with density varying over a voxel grid, such as clouds or explosions
+pub struct GridVolume {
+    bounds: Aabb,
+    resolution: [usize; 3],
+    // X varies fastest, then Y, then Z
+    densities: Vec<Float>,
+    // Upper bound on density, used as the majorant for tracking
+    max_density: Float,
+    phase: Arc<dyn Material + Send + Sync>,
+}
+
+impl GridVolume {
+    /// `densities` has one value per voxel, with X varying fastest and then Y.
+    /// Densities are scaled by `density_scale`, the chance per unit distance for a ray to scatter at a density of 1.
+    pub fn new(bounds: Aabb, resolution: [usize; 3], densities: Vec<Float>, density_scale: Float, phase: Arc<dyn Material + Send + Sync>) -> Self {
+        assert!(resolution.iter().all(|&r| r > 0), "Volume grids must have at least one voxel on each axis");
+        // Lookups divide by the extent, which flat or inverted bounds would make meaningless
+        assert!(has_extent(&bounds), "Volume grids must have bounds with a positive extent on each axis");
+        assert_eq!(Some(densities.len()), voxel_count(resolution), "Volume grids must have one density per voxel");
+        let densities: Vec<Float> = densities.iter().map(|d| d.max(0.0) * density_scale).collect();
+        let max_density = densities.iter().cloned().fold(0.0, Float::max);
+        Self {
+            bounds,
+            resolution,
+            densities,
+            max_density,
+            phase
+        }
+    }
+
+    /// Loads a grid stored as raw little endian `f32`s, with X varying fastest and then Y
+    pub fn load_raw(path: impl AsRef<Path>, bounds: Aabb, resolution: [usize; 3], density_scale: Float, phase: Arc<dyn Material + Send + Sync>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if Some(bytes.len()) != grid_bytes(resolution) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "raw volume size does not match its resolution"));
+        }
+        Ok(Self::new(bounds, resolution, read_f32s(&bytes), density_scale, phase))
+    }
+
+    /// Loads a grid from a Mitsuba style `.vol` file of single channel `f32` densities.
+    /// The bounds of the volume come from the file.
+    pub fn load_vol(path: impl AsRef<Path>, density_scale: Float, phase: Arc<dyn Material + Send + Sync>) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let bytes = fs::read(path)?;
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(invalid("not a version 3 .vol file"));
+        }
+
+        let header: Vec<i32> = bytes[4..24].chunks_exact(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        if header[0] != 1 {
+            return Err(invalid(".vol files must be encoded as f32"));
+        }
+        if header[4] != 1 {
+            return Err(invalid(".vol files must have a single density channel"));
+        }
+        if header[1..4].iter().any(|&r| r <= 0) {
+            return Err(invalid(".vol resolution must be positive"));
+        }
+        let resolution = [header[1] as usize, header[2] as usize, header[3] as usize];
+
+        let corners = read_f32s(&bytes[24..48]);
+        let bounds = Aabb::new(&Vec3(corners[0], corners[1], corners[2]), &Vec3(corners[3], corners[4], corners[5]));
+        if !has_extent(&bounds) {
+            return Err(invalid(".vol bounds must have a positive extent on each axis"));
+        }
+
+        let data = &bytes[48..];
+        if Some(data.len()) != grid_bytes(resolution) {
+            return Err(invalid(".vol data size does not match its resolution"));
+        }
+        Ok(Self::new(bounds, resolution, read_f32s(data), density_scale, phase))
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> Float {
+        self.densities[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+
+    /// Density at a point, interpolated between voxel centers
+    pub fn density(&self, point: &Point) -> Float {
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let extent = self.bounds.max[axis] - self.bounds.min[axis];
+            let res = self.resolution[axis];
+            let g = ((point[axis] - self.bounds.min[axis]) / extent * res as Float - 0.5).max(0.0).min((res - 1) as Float);
+            lower[axis] = g.floor() as usize;
+            upper[axis] = (lower[axis] + 1).min(res - 1);
+            frac[axis] = g - g.floor();
+        }
+
+        let lerp = |a: Float, b: Float, t: Float| (1.0 - t) * a + t * b;
+        let x = |y: usize, z: usize| lerp(self.voxel(lower[0], y, z), self.voxel(upper[0], y, z), frac[0]);
+        let y = |z: usize| lerp(x(lower[1], z), x(upper[1], z), frac[1]);
+        lerp(y(lower[2]), y(upper[2]), frac[2])
+    }
+
+    /// The part of the range inside the grid, if any
+    fn clip(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<(Float, Float)> {
+        let ((enter, _), (exit, _)) = self.bounds.slab(ray)?;
+        let enter = enter.max(tmin);
+        let exit = exit.min(tmax);
+        if enter < exit {
+            Some((enter, exit))
+        } else {
+            None
+        }
+    }
+}
+
+impl Hittable for GridVolume {
+    /// Samples a scattering point with delta tracking against the largest density in the grid
+    fn hit(&self, ray: &Ray, tmin: Float, tmax: Float) -> Option<HitRecord<'_>> {
+        let (mut t, exit) = self.clip(ray, tmin, tmax)?;
+        if self.max_density <= 0.0 {
+            return None;
+        }
+
+        // Measured along the ray's parameter rather than in world units
+        let majorant = self.max_density * ray.direction().length();
+        loop {
+            t -= (1.0 - rand::random::<Float>()).ln() / majorant;
+            if t >= exit {
+                return None;
+            }
+            let point = ray.at(t);
+            if rand::random::<Float>() * self.max_density < self.density(&point) {
+                return Some(HitRecord::new(ray, t, -ray.direction().normalize(), self.phase.as_ref()));
+            }
+        }
+    }
+
+    /// Estimates the fraction of light passing through the range unscattered with ratio tracking,
+    /// which weighs every tentative collision instead of stopping at one, so shadow rays are less noisy
+    fn transmittance(&self, ray: &Ray, tmin: Float, tmax: Float) -> Float {
+        let (mut t, exit) = match self.clip(ray, tmin, tmax) {
+            Some(range) if self.max_density > 0.0 => range,
+            _ => return 1.0
+        };
+
+        let majorant = self.max_density * ray.direction().length();
+        let mut transmittance = 1.0;
+        loop {
+            t -= (1.0 - rand::random::<Float>()).ln() / majorant;
+            if t >= exit {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density(&ray.at(t)) / self.max_density;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+/// Whether the bounds are wider than a point on every axis, so that voxels have a size
+fn has_extent(bounds: &Aabb) -> bool {
+    (0..3).all(|axis| bounds.max[axis] > bounds.min[axis])
+}
+
+/// Number of voxels in a grid, or `None` if it is too large to count, as a corrupt header could claim
+fn voxel_count(resolution: [usize; 3]) -> Option<usize> {
+    resolution.iter().try_fold(1usize, |count, &r| count.checked_mul(r))
+}
+
+/// Size of a grid of `f32`s in bytes
+fn grid_bytes(resolution: [usize; 3]) -> Option<usize> {
+    voxel_count(resolution)?.checked_mul(4)
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<Float> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as Float).collect()
+}